@@ -0,0 +1,36 @@
+//! Creates the scope a call binds its arguments in: chained to the root, as calls do, and
+//! rebuilt from `core::ns()`, as every call used to. `env::tests::scopes_chain_to_the_root`
+//! checks that calls take the first path; this shows what the second one cost.
+//!
+//! Run with `cargo bench --bench calls`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use mal::env::Env;
+use mal::MalType;
+use std::rc::Rc;
+
+fn scopes(c: &mut Criterion) {
+    let root = Rc::new(Env::default());
+    let mut group = c.benchmark_group("call scope");
+    group.bench_function("chained to the root", |b| {
+        b.iter(|| {
+            Env::new(
+                Some(root.clone()),
+                vec![MalType::Sym("a".to_string(), None)],
+                vec![black_box(MalType::Int(1))],
+            )
+            .unwrap()
+        })
+    });
+    group.bench_function("rebuilt from core::ns()", |b| {
+        b.iter(|| {
+            let env = Env::default();
+            env.set("a", black_box(MalType::Int(1)));
+            env
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, scopes);
+criterion_main!(benches);
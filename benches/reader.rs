@@ -0,0 +1,68 @@
+//! Reads a 1 MB source with the lexer and with the regex tokenizer it replaced.
+//!
+//! Run with `cargo bench --bench reader`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use mal::reader::read_all;
+use regex::Regex;
+
+const CHUNK: &str = r#"
+; Fibonacci, the slow way
+(def! fib (fn* (n)
+  (if (<= n 1)
+    n
+    (+ (fib (- n 1)) (fib (- n 2))))))
+
+(def! config {:name "mal" :version [0 1 0] :tags '(lisp "rust, too")})
+(defmacro! unless (fn* (pred a b) `(if ~pred ~b ~a)))
+(let* (xs [1 2 3 -4 5] s "escaped \"quotes\" and \\ slashes\n")
+  (map (fn* (x) (* x x)) xs))
+"#;
+
+fn source() -> String {
+    CHUNK.repeat((1 << 20) / CHUNK.len() + 1)
+}
+
+/// The tokenizer and atom classification the reader used before the lexer: one regex
+/// compiled per call to split the source, and three more compiled for every atom, tried in
+/// turn for a number, a string and a keyword.
+#[allow(clippy::regex_creation_in_loops)]
+fn regex_tokens(s: &str) -> usize {
+    let reg =
+        Regex::new(r###"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]*)"###)
+            .expect("Invalid regular expression provided");
+    let mut atoms = 0;
+    for cap in reg.captures_iter(s) {
+        let token = &cap[1];
+        if token.starts_with(';') || token.is_empty() {
+            continue;
+        }
+        if matches!(
+            token,
+            "(" | ")" | "[" | "]" | "{" | "}" | "'" | "`" | "~" | "~@" | "@" | "^"
+        ) {
+            continue;
+        }
+        let num_re = Regex::new(r"^-?[0-9]+$").expect("Invalid regular expression for number");
+        let str_re = Regex::new(r#"^"(.)*"$"#).expect("Invalid regular expression for string");
+        let key_re = Regex::new(r"^:(.*)*$").expect("Invalid regular expression for keyword");
+        if num_re.is_match(token) || str_re.is_match(token) || key_re.is_match(token) {
+            atoms += 1;
+        }
+    }
+    atoms
+}
+
+fn read(c: &mut Criterion) {
+    let src = source();
+    let mut group = c.benchmark_group("read 1 MB");
+    group.sample_size(10);
+    group.bench_function("lexer", |b| b.iter(|| read_all(black_box(&src)).unwrap()));
+    group.bench_function("regex tokenizer", |b| {
+        b.iter(|| regex_tokens(black_box(&src)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, read);
+criterion_main!(benches);
@@ -0,0 +1,381 @@
+//! Conversions between [`MalType`] and Rust values, used to register plain Rust functions as
+//! Mal builtins without matching on `MalType` by hand.
+
+use crate::types::{MalErr, MalType, MapKey};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
+use std::any::{self, Any};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub trait FromMal: Sized {
+    fn from_mal(val: &MalType) -> Result<Self, MalErr>;
+}
+
+/// Conversion into a Mal value. It may fail, e.g. for a Rust integer that does not fit.
+pub trait IntoMal {
+    fn into_mal(self) -> Result<MalType, MalErr>;
+}
+
+fn wrong_type(expected: &str, found: &MalType) -> MalErr {
+    MalErr::WrongType {
+        expected: expected.to_string(),
+        found: found.clone(),
+    }
+}
+
+impl FromMal for MalType {
+    fn from_mal(val: &MalType) -> Result<Self, MalErr> {
+        Ok(val.clone())
+    }
+}
+
+impl IntoMal for MalType {
+    fn into_mal(self) -> Result<MalType, MalErr> {
+        Ok(self)
+    }
+}
+
+impl FromMal for i64 {
+    fn from_mal(val: &MalType) -> Result<Self, MalErr> {
+        match val {
+            MalType::Int(n) => Ok(*n),
+            _ => Err(wrong_type("integer", val)),
+        }
+    }
+}
+
+impl IntoMal for i64 {
+    fn into_mal(self) -> Result<MalType, MalErr> {
+        Ok(MalType::Int(self))
+    }
+}
+
+/// Integers are promoted, as they are by the arithmetic builtins.
+impl FromMal for f64 {
+    fn from_mal(val: &MalType) -> Result<Self, MalErr> {
+        match val {
+            MalType::Int(n) => Ok(*n as f64),
+            MalType::Bignum(n) => Ok(n.to_f64().unwrap_or(f64::NAN)),
+            MalType::Ratio(r) => Ok(r.to_f64().unwrap_or(f64::NAN)),
+            MalType::Float(f) => Ok(*f),
+            _ => Err(wrong_type("number", val)),
+        }
+    }
+}
+
+impl IntoMal for f64 {
+    fn into_mal(self) -> Result<MalType, MalErr> {
+        Ok(MalType::Float(self))
+    }
+}
+
+impl FromMal for BigInt {
+    fn from_mal(val: &MalType) -> Result<Self, MalErr> {
+        match val {
+            MalType::Int(n) => Ok(BigInt::from(*n)),
+            MalType::Bignum(n) => Ok(n.as_ref().clone()),
+            _ => Err(wrong_type("integer", val)),
+        }
+    }
+}
+
+impl IntoMal for BigInt {
+    fn into_mal(self) -> Result<MalType, MalErr> {
+        Ok(MalType::bignum(self))
+    }
+}
+
+/// Any exact number, so that integers can be passed where a fraction is expected.
+impl FromMal for BigRational {
+    fn from_mal(val: &MalType) -> Result<Self, MalErr> {
+        match val {
+            MalType::Ratio(r) => Ok(r.as_ref().clone()),
+            _ => BigInt::from_mal(val)
+                .map(BigRational::from_integer)
+                .map_err(|_| wrong_type("exact number", val)),
+        }
+    }
+}
+
+impl IntoMal for BigRational {
+    fn into_mal(self) -> Result<MalType, MalErr> {
+        Ok(MalType::ratio(self))
+    }
+}
+
+impl FromMal for bool {
+    fn from_mal(val: &MalType) -> Result<Self, MalErr> {
+        match val {
+            MalType::Bool(b) => Ok(*b),
+            _ => Err(wrong_type("boolean", val)),
+        }
+    }
+}
+
+impl IntoMal for bool {
+    fn into_mal(self) -> Result<MalType, MalErr> {
+        Ok(MalType::Bool(self))
+    }
+}
+
+impl FromMal for String {
+    fn from_mal(val: &MalType) -> Result<Self, MalErr> {
+        match val {
+            MalType::Str(s) => Ok(s.clone()),
+            _ => Err(wrong_type("string", val)),
+        }
+    }
+}
+
+impl IntoMal for String {
+    fn into_mal(self) -> Result<MalType, MalErr> {
+        Ok(MalType::Str(self))
+    }
+}
+
+impl IntoMal for &str {
+    fn into_mal(self) -> Result<MalType, MalErr> {
+        Ok(MalType::Str(self.to_string()))
+    }
+}
+
+impl IntoMal for () {
+    fn into_mal(self) -> Result<MalType, MalErr> {
+        Ok(MalType::Nil)
+    }
+}
+
+/// Accepts both lists and vectors.
+impl<T: FromMal> FromMal for Vec<T> {
+    fn from_mal(val: &MalType) -> Result<Self, MalErr> {
+        match val {
+            MalType::List(l, _) | MalType::Vector(l, _) => l.iter().map(T::from_mal).collect(),
+            _ => Err(wrong_type("list or vector", val)),
+        }
+    }
+}
+
+/// Converts into a list.
+impl<T: IntoMal> IntoMal for Vec<T> {
+    fn into_mal(self) -> Result<MalType, MalErr> {
+        let vec = self
+            .into_iter()
+            .map(T::into_mal)
+            .collect::<Result<_, _>>()?;
+        Ok(MalType::List(Rc::new(vec), None))
+    }
+}
+
+/// `nil` is `None`, anything else has to convert to `T`.
+impl<T: FromMal> FromMal for Option<T> {
+    fn from_mal(val: &MalType) -> Result<Self, MalErr> {
+        match val {
+            MalType::Nil => Ok(None),
+            _ => T::from_mal(val).map(Some),
+        }
+    }
+}
+
+impl<T: IntoMal> IntoMal for Option<T> {
+    fn into_mal(self) -> Result<MalType, MalErr> {
+        match self {
+            Some(val) => val.into_mal(),
+            None => Ok(MalType::Nil),
+        }
+    }
+}
+
+/// Keyword keys keep their leading `:`, so `{:a 1 "b" 2}` has the keys `":a"` and `"b"`. A map
+/// with both `:a` and `":a"` as keys is an error, as they would become the same key.
+impl<T: FromMal> FromMal for HashMap<String, T> {
+    fn from_mal(val: &MalType) -> Result<Self, MalErr> {
+        match val {
+            MalType::HashMap(map, _) => {
+                let mut ret = HashMap::new();
+                for (k, v) in map.iter() {
+                    let (MapKey::Str(key) | MapKey::Keyword(key)) = k;
+                    if ret.insert(key.clone(), T::from_mal(v)?).is_some() {
+                        return Err(MalErr::E(format!(
+                            "Hash-map has both a string and a keyword for the key `{key}'"
+                        )));
+                    }
+                }
+                Ok(ret)
+            }
+            _ => Err(wrong_type("hash-map", val)),
+        }
+    }
+}
+
+/// Keys become strings.
+impl<T: IntoMal> IntoMal for HashMap<String, T> {
+    fn into_mal(self) -> Result<MalType, MalErr> {
+        let map = self
+            .into_iter()
+            .map(|(k, v)| Ok((MapKey::Str(k), v.into_mal()?)))
+            .collect::<Result<_, MalErr>>()?;
+        Ok(MalType::HashMap(Rc::new(map), None))
+    }
+}
+
+/// Host values travel through Mal as [`MalType::Opaque`].
+impl<T: Any> FromMal for Rc<T> {
+    fn from_mal(val: &MalType) -> Result<Self, MalErr> {
+        val.downcast_rc()
+            .ok_or_else(|| wrong_type(&format!("<opaque:{}>", any::type_name::<T>()), val))
+    }
+}
+
+impl<T: Any> IntoMal for Rc<T> {
+    fn into_mal(self) -> Result<MalType, MalErr> {
+        Ok(MalType::opaque_rc(self))
+    }
+}
+
+/// Lets a registered function fail with its own error.
+impl<T: IntoMal> IntoMal for Result<T, MalErr> {
+    fn into_mal(self) -> Result<MalType, MalErr> {
+        self?.into_mal()
+    }
+}
+
+/// Converts argument `i` of the native function `name`, naming both in the error.
+fn arg<T: FromMal>(name: &str, i: usize, val: &MalType) -> Result<T, MalErr> {
+    T::from_mal(val).map_err(|e| match e {
+        MalErr::WrongType { expected, found } => MalErr::WrongType {
+            expected: format!("{expected} as argument {} to `{name}'", i + 1),
+            found,
+        },
+        e => e,
+    })
+}
+
+/// A Rust function whose arguments and result convert to and from Mal values. `Args` is the
+/// tuple of its argument types and only serves to tell the implementations apart.
+pub trait IntoNativeFn<Args> {
+    fn into_native(self, name: &str) -> MalType;
+}
+
+macro_rules! impl_into_native_fn {
+    ($arity:expr; $($arg:ident: $i:tt),*) => {
+        impl<F, R, $($arg),*> IntoNativeFn<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + 'static,
+            R: IntoMal,
+            $($arg: FromMal,)*
+        {
+            fn into_native(self, name: &str) -> MalType {
+                let fn_name = name.to_string();
+                MalType::func(name, move |args| {
+                    if args.len() != $arity {
+                        return Err(MalErr::WrongNumberOfArguments {
+                            name: fn_name.clone(),
+                            expected: $arity.to_string(),
+                            found: args.len(),
+                        });
+                    }
+                    self($(arg::<$arg>(&fn_name, $i, &args[$i])?),*).into_mal()
+                })
+            }
+        }
+    };
+}
+
+impl_into_native_fn!(0;);
+impl_into_native_fn!(1; A: 0);
+impl_into_native_fn!(2; A: 0, B: 1);
+impl_into_native_fn!(3; A: 0, B: 1, C: 2);
+impl_into_native_fn!(4; A: 0, B: 1, C: 2, D: 3);
+impl_into_native_fn!(5; A: 0, B: 1, C: 2, D: 3, E: 4);
+impl_into_native_fn!(6; A: 0, B: 1, C: 2, D: 3, E: 4, G: 5);
+
+#[cfg(test)]
+mod tests {
+    use crate::convert::{FromMal, IntoMal};
+    use crate::{Interpreter, MalErr, MalType};
+    use num_bigint::BigInt;
+    use num_rational::BigRational;
+    use std::collections::HashMap;
+
+    #[test]
+    fn conversions() {
+        let interp = Interpreter::new();
+        let val = interp.eval_str("[1 2 3]").unwrap();
+        assert_eq!(vec![1, 2, 3], Vec::<i64>::from_mal(&val).unwrap());
+        let val = interp.eval_str("(list nil 2)").unwrap();
+        assert_eq!(
+            vec![None, Some(2)],
+            Vec::<Option<i64>>::from_mal(&val).unwrap()
+        );
+        let val = interp.eval_str(r#"{:a "x" "b" "y"}"#).unwrap();
+        let map = HashMap::<String, String>::from_mal(&val).unwrap();
+        assert_eq!(Some(&"x".to_string()), map.get(":a"));
+        assert_eq!(Some(&"y".to_string()), map.get("b"));
+        let val = interp.eval_str(r#"{:a 1 ":a" 2}"#).unwrap();
+        assert!(HashMap::<String, i64>::from_mal(&val).is_err());
+        assert!(bool::from_mal(&MalType::Nil).is_err());
+        assert_eq!(2.0, f64::from_mal(&MalType::Int(2)).unwrap());
+        assert!(i64::from_mal(&MalType::Float(2.0)).is_err());
+        let val = interp.eval_str("(/ 1 3)").unwrap();
+        let third = BigRational::from_mal(&val).unwrap();
+        assert_eq!(
+            "1",
+            (third * BigInt::from(3)).into_mal().unwrap().pr_str(true)
+        );
+        assert!(BigInt::from_mal(&val).is_err());
+
+        let val = vec![Some("a"), None].into_mal().unwrap();
+        assert_eq!(r#"("a" nil)"#, val.pr_str(true));
+        let val = HashMap::from([("k".to_string(), true)]).into_mal().unwrap();
+        assert_eq!(r#"{"k" true}"#, val.pr_str(true));
+    }
+
+    #[test]
+    fn register_fn() {
+        let interp = Interpreter::new();
+        interp.register_fn("add", |a: i64, b: i64| a + b);
+        interp.register_fn("shout", |s: String| s.to_uppercase());
+        interp.register_fn("total", |v: Vec<i64>| v.iter().sum::<i64>());
+        interp.register_fn("or-default", |a: Option<i64>| a.unwrap_or(-1));
+        interp.register_fn("answer", || 42);
+        interp.register_fn("checked-div", |a: i64, b: i64| {
+            a.checked_div(b)
+                .ok_or_else(|| MalErr::E("Division by zero".to_string()))
+        });
+
+        let hash = HashMap::from([
+            ("(add 1 2)", "3"),
+            (r#"(shout "hi")"#, r#""HI""#),
+            ("(total [1 2 3])", "6"),
+            ("(or-default nil)", "-1"),
+            ("(or-default 5)", "5"),
+            ("(answer)", "42"),
+            ("(map (fn* (a) (add a 1)) [1 2])", "(2 3)"),
+            ("(checked-div 6 3)", "2"),
+        ]);
+        for (input, output) in hash {
+            assert_eq!(output, interp.eval_str(input).unwrap().pr_str(true));
+        }
+
+        let hash = HashMap::from([
+            (
+                "(add 1)",
+                "Wrong number of arguments to `add': expected 2, found 1",
+            ),
+            (
+                r#"(add 1 "2")"#,
+                r#"Expected integer as argument 2 to `add' but found "2""#,
+            ),
+            (
+                "(total [1 :a])",
+                "Expected integer as argument 1 to `total' but found :a",
+            ),
+            ("(checked-div 1 0)", "Division by zero"),
+        ]);
+        for (input, output) in hash {
+            assert_eq!(output, interp.eval_str(input).unwrap_err().to_string());
+        }
+    }
+}
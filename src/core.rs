@@ -1,148 +1,772 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 
-use crate::types::MalErr;
-use crate::MalType::{self, Bool, Func, Int, List, Nil, Str};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
+
+use crate::reader::{read_source, read_str};
+use crate::types::{Context, MalErr, MapKey};
+use crate::MalType::{self, Atom, Bignum, Bool, Float, Int, List, Nil, Ratio, Str, Vector};
+
+/// Inserts the alternating keys and values of `kvs` into `map`.
+fn assoc(mut map: HashMap<MapKey, MalType>, kvs: &[MalType]) -> Result<MalType, MalErr> {
+    if !kvs.len().is_multiple_of(2) {
+        return Err(MalErr::E(format!(
+            "Hash-map needs an even number of keys and values, found {}",
+            kvs.len()
+        )));
+    }
+    for pair in kvs.chunks(2) {
+        map.insert(pair[0].to_key()?, pair[1].clone());
+    }
+    Ok(MalType::HashMap(Rc::new(map), None))
+}
+
+static GENSYM_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+type Builtin = fn(&[MalType]) -> Result<MalType, MalErr>;
+type HigherOrder = fn(&Context, &[MalType]) -> Result<MalType, MalErr>;
+
+/// Orders the values `sort-by` knows how to compare.
+fn compare(a: &MalType, b: &MalType) -> Result<Ordering, MalErr> {
+    let ord = match (a, b) {
+        (a, b) if is_number(a) && is_number(b) => num_cmp(a, b),
+        (Str(a), Str(b)) => Some(a.cmp(b)),
+        (MalType::Keyword(a), MalType::Keyword(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+    ord.ok_or_else(|| {
+        MalErr::E(format!(
+            "Cannot compare {} with {}",
+            a.pr_str(true),
+            b.pr_str(true)
+        ))
+    })
+}
+
+fn is_number(val: &MalType) -> bool {
+    matches!(val, Int(_) | Bignum(_) | Ratio(_) | Float(_))
+}
+
+/// The arguments of the operator `op`, which must all be numbers.
+fn numbers<'a>(op: &str, vec: &'a [MalType]) -> Result<&'a [MalType], MalErr> {
+    match vec.iter().all(is_number) {
+        true => Ok(vec),
+        false => Err(MalErr::E(format!(
+            "Wrong number or type of arguments provided to operator `{op}'"
+        ))),
+    }
+}
+
+/// A number as a float, the nearest one for exact numbers.
+fn to_f64(val: &MalType) -> f64 {
+    match val {
+        Int(i) => *i as f64,
+        Bignum(n) => n.to_f64().unwrap_or(f64::NAN),
+        Ratio(r) => r.to_f64().unwrap_or(f64::NAN),
+        Float(f) => *f,
+        _ => unreachable!("Only called on numbers"),
+    }
+}
+
+/// A number as a ratio, without rounding. Floats must be finite.
+fn to_ratio(val: &MalType) -> BigRational {
+    match val {
+        Int(i) => BigRational::from_integer(BigInt::from(*i)),
+        Bignum(n) => BigRational::from_integer(n.as_ref().clone()),
+        Ratio(r) => r.as_ref().clone(),
+        Float(f) => BigRational::from_float(*f).expect("Only called on finite floats"),
+        _ => unreachable!("Only called on numbers"),
+    }
+}
+
+/// Compares two numbers by their exact values, so that large integers do not round to the
+/// float they are compared with. `None` when either is NaN.
+fn num_cmp(a: &MalType, b: &MalType) -> Option<Ordering> {
+    match (a, b) {
+        (Int(a), Int(b)) => Some(a.cmp(b)),
+        (Float(a), Float(b)) => a.partial_cmp(b),
+        // Infinities and NaN compare with any exact number as they do with zero.
+        (Float(a), _) if !a.is_finite() => a.partial_cmp(&0.0),
+        (_, Float(b)) if !b.is_finite() => 0.0.partial_cmp(b),
+        _ => Some(to_ratio(a).cmp(&to_ratio(b))),
+    }
+}
+
+fn at_least_one(op: &str, vec: &[MalType]) -> MalErr {
+    MalErr::WrongNumberOfArguments {
+        name: op.to_string(),
+        expected: "at least 1".to_string(),
+        found: vec.len(),
+    }
+}
+
+fn division_by_zero() -> MalErr {
+    MalErr::E("Division by zero".to_string())
+}
+
+/// An operation on integers, on exact numbers and on floats. The first two give `None` when
+/// dividing by zero, and the one on integers also when the result is not an `i64`.
+type NumOp = (
+    fn(i64, i64) -> Option<i64>,
+    fn(&BigRational, &BigRational) -> Option<BigRational>,
+    fn(f64, f64) -> f64,
+);
+
+/// Applies an operation to two numbers. Integers whose result does not fit an `i64` are
+/// promoted to bignums or ratios, and anything mixed with a float to a float.
+fn num_op(a: &MalType, b: &MalType, (int_f, exact_f, float_f): NumOp) -> Result<MalType, MalErr> {
+    if let (Int(a), Int(b)) = (a, b) {
+        if let Some(res) = int_f(*a, *b) {
+            return Ok(Int(res));
+        }
+    }
+    match (a, b) {
+        (Float(_), _) | (_, Float(_)) => Ok(Float(float_f(to_f64(a), to_f64(b)))),
+        _ => exact_f(&to_ratio(a), &to_ratio(b))
+            .map(MalType::ratio)
+            .ok_or_else(division_by_zero),
+    }
+}
+
+/// Folds the arguments of `op` from the left with `f`. As in Clojure, `(op)` is `identity` and
+/// `(op x)` is `(op identity x)`, so `(- x)` negates `x`.
+fn arithmetic(op: &str, vec: &[MalType], identity: i64, f: NumOp) -> Result<MalType, MalErr> {
+    match numbers(op, vec)? {
+        [] => Ok(Int(identity)),
+        [x] => num_op(&Int(identity), x, f),
+        [first, rest @ ..] => rest
+            .iter()
+            .try_fold(first.clone(), |acc, x| num_op(&acc, x, f)),
+    }
+}
+
+/// An operator taking exactly two numbers, like `mod`.
+fn binary(op: &str, vec: &[MalType], f: NumOp) -> Result<MalType, MalErr> {
+    match numbers(op, vec)? {
+        [a, b] => num_op(a, b, f),
+        _ => Err(MalErr::WrongNumberOfArguments {
+            name: op.to_string(),
+            expected: "2".to_string(),
+            found: vec.len(),
+        }),
+    }
+}
+
+/// An operator taking exactly one number, like `abs`.
+fn unary(
+    op: &str,
+    vec: &[MalType],
+    f: fn(&MalType) -> Result<MalType, MalErr>,
+) -> Result<MalType, MalErr> {
+    match numbers(op, vec)? {
+        [a] => f(a),
+        _ => Err(MalErr::WrongNumberOfArguments {
+            name: op.to_string(),
+            expected: "1".to_string(),
+            found: vec.len(),
+        }),
+    }
+}
+
+/// The largest exact result `pow` computes, in bits of its numerator or denominator. Anything
+/// larger would take the interpreter seconds or gigabytes to compute.
+const MAX_POW_BITS: u64 = 1 << 20;
+
+/// `base` to the power of `exp`. It stays exact for an exact base and an integer exponent, so
+/// `(pow 2 -1)` is `1/2`.
+fn pow(base: &MalType, exp: &MalType) -> Result<MalType, MalErr> {
+    match (base, exp) {
+        (Float(_), _) | (_, Float(_) | Ratio(_)) => Ok(Float(to_f64(base).powf(to_f64(exp)))),
+        (Int(a), Int(b)) if u32::try_from(*b).is_ok_and(|b| a.checked_pow(b).is_some()) => {
+            Ok(Int(a.pow(*b as u32)))
+        }
+        _ => {
+            let exp = match exp {
+                Int(exp) => i32::try_from(*exp).ok(),
+                _ => None,
+            }
+            .ok_or_else(|| MalErr::E(format!("Exponent too large: {}", exp.pr_str(true))))?;
+            let base = to_ratio(base);
+            if base.is_zero() && exp < 0 {
+                return Err(division_by_zero());
+            }
+            // A lower bound on the size of the result, exact for powers of two.
+            let bits = base
+                .numer()
+                .bits()
+                .max(base.denom().bits())
+                .saturating_sub(1);
+            if bits.saturating_mul(u64::from(exp.unsigned_abs())) > MAX_POW_BITS {
+                return Err(MalErr::E(format!(
+                    "Result of `pow' too large: {} to the power of {exp}",
+                    MalType::ratio(base).pr_str(true)
+                )));
+            }
+            Ok(MalType::ratio(base.pow(exp)))
+        }
+    }
+}
+
+/// Rounds a float with `float_f` and a ratio with `exact_f`, leaving integers as they are.
+fn round_with(
+    val: &MalType,
+    float_f: fn(f64) -> f64,
+    exact_f: fn(&BigRational) -> BigRational,
+) -> Result<MalType, MalErr> {
+    match val {
+        Float(x) => Ok(Float(float_f(*x))),
+        Ratio(r) => Ok(MalType::ratio(exact_f(r))),
+        _ => Ok(val.clone()),
+    }
+}
+
+/// The smallest or largest of the arguments of `op`, kept as they are rather than promoted.
+fn extreme(op: &str, vec: &[MalType], keep: fn(Ordering) -> bool) -> Result<MalType, MalErr> {
+    match numbers(op, vec)? {
+        [] => Err(at_least_one(op, vec)),
+        [first, rest @ ..] => Ok(rest
+            .iter()
+            .fold(first, |acc, x| {
+                // NaN wins, as it compares neither way with anything.
+                match num_cmp(x, acc) {
+                    Some(ord) if !keep(ord) => acc,
+                    _ if matches!(acc, Float(f) if f.is_nan()) => acc,
+                    _ => x,
+                }
+            })
+            .clone()),
+    }
+}
+
+/// Whether `f` holds between every pair of neighbouring arguments, e.g. `(< 1 2 3)`. Nothing
+/// holds for NaN.
+fn chain(op: &str, vec: &[MalType], f: fn(Ordering) -> bool) -> Result<MalType, MalErr> {
+    let nums = numbers(op, vec)?;
+    if nums.is_empty() {
+        return Err(at_least_one(op, vec));
+    }
+    Ok(Bool(
+        nums.windows(2)
+            .all(|pair| num_cmp(&pair[0], &pair[1]).is_some_and(f)),
+    ))
+}
 
 pub fn ns() -> Vec<(&'static str, MalType)> {
-    vec![
-        (
-            "+",
-            Func(|vec| match vec[..] {
-                [Int(a), Int(b)] => Ok(Int(a + b)),
-                _ => Err(MalErr::E(
-                    "Wrong number or type of arguments provided to operator `+'".to_string(),
-                )),
-            }),
-        ),
-        (
-            "-",
-            Func(|vec| match vec[..] {
-                [Int(a), Int(b)] => Ok(Int(a - b)),
-                _ => Err(MalErr::E(
-                    "Wrong number or type of arguments provided to operator `-'".to_string(),
-                )),
+    let builtins: Vec<(&'static str, Builtin)> = vec![
+        ("+", |vec| {
+            arithmetic(
+                "+",
+                vec,
+                0,
+                (i64::checked_add, |a, b| Some(a + b), |a, b| a + b),
+            )
+        }),
+        ("-", |vec| match vec {
+            [] => Err(at_least_one("-", vec)),
+            _ => arithmetic(
+                "-",
+                vec,
+                0,
+                (i64::checked_sub, |a, b| Some(a - b), |a, b| a - b),
+            ),
+        }),
+        ("*", |vec| {
+            arithmetic(
+                "*",
+                vec,
+                1,
+                (i64::checked_mul, |a, b| Some(a * b), |a, b| a * b),
+            )
+        }),
+        // Dividing integers that do not divide evenly gives a ratio.
+        ("/", |vec| match vec {
+            [] => Err(at_least_one("/", vec)),
+            _ => arithmetic(
+                "/",
+                vec,
+                1,
+                (
+                    |a, b| a.checked_rem(b).filter(|r| *r == 0).and(a.checked_div(b)),
+                    |a, b| (!b.is_zero()).then(|| a / b),
+                    |a, b| a / b,
+                ),
+            ),
+        }),
+        ("quot", |vec| {
+            binary(
+                "quot",
+                vec,
+                (
+                    i64::checked_div,
+                    |a, b| (!b.is_zero()).then(|| (a / b).trunc()),
+                    |a, b| (a / b).trunc(),
+                ),
+            )
+        }),
+        ("rem", |vec| {
+            binary(
+                "rem",
+                vec,
+                (
+                    i64::checked_rem,
+                    |a, b| (!b.is_zero()).then(|| a - b * (a / b).trunc()),
+                    |a, b| a % b,
+                ),
+            )
+        }),
+        // Unlike `rem`, the result takes the sign of the divisor.
+        ("mod", |vec| {
+            binary(
+                "mod",
+                vec,
+                (
+                    |a, b| {
+                        a.checked_rem(b).map(|r| {
+                            if r != 0 && (r < 0) != (b < 0) {
+                                r + b
+                            } else {
+                                r
+                            }
+                        })
+                    },
+                    |a, b| (!b.is_zero()).then(|| a - b * (a / b).floor()),
+                    |a, b| {
+                        let r = a % b;
+                        if r != 0.0 && (r < 0.0) != (b < 0.0) {
+                            r + b
+                        } else {
+                            r
+                        }
+                    },
+                ),
+            )
+        }),
+        ("pow", |vec| match numbers("pow", vec)? {
+            [base, exp] => pow(base, exp),
+            _ => Err(MalErr::WrongNumberOfArguments {
+                name: "pow".to_string(),
+                expected: "2".to_string(),
+                found: vec.len(),
             }),
-        ),
-        (
-            "*",
-            Func(|vec| match vec[..] {
-                [Int(a), Int(b)] => Ok(Int(a * b)),
-                _ => Err(MalErr::E(
-                    "Wrong number or type of arguments provided to operator `*'".to_string(),
-                )),
+        }),
+        ("abs", |vec| {
+            unary("abs", vec, |a| match a {
+                Int(i) => Ok(i
+                    .checked_abs()
+                    .map(Int)
+                    .unwrap_or_else(|| MalType::bignum(BigInt::from(*i).abs()))),
+                Bignum(n) => Ok(MalType::bignum(n.abs())),
+                Ratio(r) => Ok(MalType::ratio(r.abs())),
+                _ => Ok(Float(to_f64(a).abs())),
+            })
+        }),
+        ("min", |vec| extreme("min", vec, Ordering::is_lt)),
+        ("max", |vec| extreme("max", vec, Ordering::is_gt)),
+        ("floor", |vec| {
+            unary("floor", vec, |a| {
+                round_with(a, f64::floor, BigRational::floor)
+            })
+        }),
+        ("ceil", |vec| {
+            unary("ceil", vec, |a| round_with(a, f64::ceil, BigRational::ceil))
+        }),
+        // Halfway cases round away from zero.
+        ("round", |vec| {
+            unary("round", vec, |a| {
+                round_with(a, f64::round, BigRational::round)
+            })
+        }),
+        ("sqrt", |vec| {
+            unary("sqrt", vec, |a| Ok(Float(to_f64(a).sqrt())))
+        }),
+        ("float", |vec| unary("float", vec, |a| Ok(Float(to_f64(a))))),
+        // Truncates towards zero.
+        ("int", |vec| {
+            unary("int", vec, |a| match a {
+                Float(f) => BigInt::from_f64(f.trunc())
+                    .map(MalType::bignum)
+                    .ok_or_else(|| {
+                        MalErr::E(format!("Cannot convert {} to an integer", a.pr_str(true)))
+                    }),
+                Ratio(r) => Ok(MalType::bignum(r.to_integer())),
+                _ => Ok(a.clone()),
+            })
+        }),
+        ("=", |vec| match vec {
+            [] => Err(at_least_one("=", vec)),
+            _ => Ok(Bool(vec.windows(2).all(|pair| pair[0] == pair[1]))),
+        }),
+        ("<", |vec| chain("<", vec, Ordering::is_lt)),
+        ("<=", |vec| chain("<=", vec, Ordering::is_le)),
+        (">", |vec| chain(">", vec, Ordering::is_gt)),
+        (">=", |vec| chain(">=", vec, Ordering::is_ge)),
+        ("count", |vec| {
+            if vec.is_empty() {
+                return Err(MalErr::E(
+                    "Possibly wrong number of arguments provided to `count'".to_string(),
+                ));
+            }
+            match &vec[0] {
+                List(l, _) | Vector(l, _) => Ok(Int(l.len() as i64)),
+                _ => Ok(Int(1)),
+            }
+        }),
+        ("list", |vec| {
+            let mut ret = Vec::new();
+            for each in vec {
+                ret.push(each.clone());
+            }
+            Ok(List(Rc::new(ret), None))
+        }),
+        ("list?", |vec| {
+            if vec.is_empty() {
+                return Err(MalErr::E(
+                    "Possibly wrong number of arguments provided to `list?'".to_string(),
+                ));
+            }
+            match &vec[0] {
+                List(..) => Ok(Bool(true)),
+                _ => Ok(Bool(false)),
+            }
+        }),
+        ("empty?", |vec| {
+            if vec.is_empty() {
+                return Err(MalErr::E(
+                    "Possibly wrong number of arguments provided to `empty?'".to_string(),
+                ));
+            }
+            match &vec[0] {
+                List(l, _) | Vector(l, _) => Ok(Bool(l.is_empty())),
+                _ => Ok(Bool(false)),
+            }
+        }),
+        ("pr-str", |vec| {
+            let ret: Vec<String> = vec.iter().map(|x| x.pr_str(true)).collect();
+            Ok(Str(ret.join(" ")))
+        }),
+        ("str", |vec| {
+            let ret: Vec<String> = vec.iter().map(|x| x.pr_str(false)).collect();
+            Ok(Str(ret.concat()))
+        }),
+        ("prn", |vec| {
+            let ret: Vec<String> = vec.iter().map(|x| x.pr_str(true)).collect();
+            println!("{}", ret.join(" "));
+            Ok(Nil)
+        }),
+        ("println", |vec| {
+            let ret: Vec<String> = vec.iter().map(|x| x.pr_str(false)).collect();
+            println!("{}", ret.join(" "));
+            Ok(Nil)
+        }),
+        ("cons", |vec| match vec {
+            [head, List(tail, _) | Vector(tail, _)] => {
+                let mut ret = vec![head.clone()];
+                ret.extend(tail.iter().cloned());
+                Ok(List(Rc::new(ret), None))
+            }
+            _ => Err(MalErr::E(
+                "`cons' expects a value and a list or vector".to_string(),
+            )),
+        }),
+        ("concat", |vec| {
+            let mut ret = Vec::new();
+            for each in vec {
+                match each {
+                    List(l, _) | Vector(l, _) => ret.extend(l.iter().cloned()),
+                    _ => {
+                        return Err(MalErr::WrongType {
+                            expected: "list or vector in `concat'".to_string(),
+                            found: each.clone(),
+                        })
+                    }
+                }
+            }
+            Ok(List(Rc::new(ret), None))
+        }),
+        ("vec", |vec| match vec {
+            [List(l, _) | Vector(l, _)] => Ok(Vector(l.clone(), None)),
+            _ => Err(MalErr::E("`vec' expects a list or vector".to_string())),
+        }),
+        ("first", |vec| match vec {
+            [List(l, _) | Vector(l, _)] => Ok(l.first().cloned().unwrap_or(Nil)),
+            [Nil] => Ok(Nil),
+            _ => Err(MalErr::E(
+                "`first' expects a list, vector or nil".to_string(),
+            )),
+        }),
+        ("rest", |vec| match vec {
+            [List(l, _) | Vector(l, _)] => {
+                Ok(List(Rc::new(l.iter().skip(1).cloned().collect()), None))
+            }
+            [Nil] => Ok(List(Rc::new(vec![]), None)),
+            _ => Err(MalErr::E(
+                "`rest' expects a list, vector or nil".to_string(),
+            )),
+        }),
+        ("nth", |vec| match vec {
+            [List(l, _) | Vector(l, _), Int(n)] => usize::try_from(*n)
+                .ok()
+                .and_then(|i| l.get(i))
+                .cloned()
+                .ok_or_else(|| {
+                    MalErr::E(format!(
+                        "Index {n} out of range for `nth' on {} items",
+                        l.len()
+                    ))
+                }),
+            _ => Err(MalErr::E(
+                "`nth' expects a list or vector and an index".to_string(),
+            )),
+        }),
+        ("vector", |vec| Ok(Vector(Rc::new(vec.to_vec()), None))),
+        ("vector?", |vec| match vec {
+            [Vector(..)] => Ok(Bool(true)),
+            [_] => Ok(Bool(false)),
+            _ => Err(MalErr::WrongNumberOfArguments {
+                name: "vector?".to_string(),
+                expected: "1".to_string(),
+                found: vec.len(),
             }),
-        ),
-        (
-            "/",
-            Func(|vec| match vec[..] {
-                [Int(a), Int(b)] => Ok(Int(a / b)),
-                _ => Err(MalErr::E(
-                    "Wrong number or type of arguments provided to operator `/'".to_string(),
-                )),
+        }),
+        ("hash-map", |vec| assoc(HashMap::new(), vec)),
+        ("map?", |vec| match vec {
+            [MalType::HashMap(..)] => Ok(Bool(true)),
+            [_] => Ok(Bool(false)),
+            _ => Err(MalErr::WrongNumberOfArguments {
+                name: "map?".to_string(),
+                expected: "1".to_string(),
+                found: vec.len(),
             }),
-        ),
-        (
-            "=",
-            Func(|vec| match &vec[..] {
-                [Nil, Nil] => Ok(Bool(true)),
-                [Bool(a), Bool(b)] => Ok(Bool(a == b)),
-                [Int(a), Int(b)] => Ok(Bool(a == b)),
-                [Str(a), Str(b)] => Ok(Bool(a == b)),
-                [List(_l1), List(_l2)] => {
-                    todo!();
+        }),
+        ("assoc", |vec| match vec {
+            [MalType::HashMap(map, _), kvs @ ..] => assoc((**map).clone(), kvs),
+            _ => Err(MalErr::E(
+                "`assoc' expects a hash-map followed by keys and values".to_string(),
+            )),
+        }),
+        ("dissoc", |vec| match vec {
+            [MalType::HashMap(map, _), keys @ ..] => {
+                let mut map = (**map).clone();
+                for key in keys {
+                    map.remove(&key.to_key()?);
                 }
-                _ => Err(MalErr::E(
-                    "Wrong number or type of arguments for `=' operator".to_string(),
-                )),
+                Ok(MalType::HashMap(Rc::new(map), None))
+            }
+            _ => Err(MalErr::E(
+                "`dissoc' expects a hash-map followed by keys".to_string(),
+            )),
+        }),
+        ("get", |vec| match vec {
+            [MalType::HashMap(map, _), key] => Ok(map.get(&key.to_key()?).cloned().unwrap_or(Nil)),
+            [Nil, _] => Ok(Nil),
+            _ => Err(MalErr::E("`get' expects a hash-map and a key".to_string())),
+        }),
+        ("contains?", |vec| match vec {
+            [MalType::HashMap(map, _), key] => Ok(Bool(map.contains_key(&key.to_key()?))),
+            _ => Err(MalErr::E(
+                "`contains?' expects a hash-map and a key".to_string(),
+            )),
+        }),
+        ("keys", |vec| match vec {
+            [MalType::HashMap(map, _)] => Ok(List(
+                Rc::new(map.keys().map(MalType::from_key).collect()),
+                None,
+            )),
+            _ => Err(MalErr::E("`keys' expects a hash-map".to_string())),
+        }),
+        ("vals", |vec| match vec {
+            [MalType::HashMap(map, _)] => Ok(List(Rc::new(map.values().cloned().collect()), None)),
+            _ => Err(MalErr::E("`vals' expects a hash-map".to_string())),
+        }),
+        ("atom", |vec| match vec {
+            [val] => Ok(Atom(Rc::new(RefCell::new(val.clone())))),
+            _ => Err(MalErr::WrongNumberOfArguments {
+                name: "atom".to_string(),
+                expected: "1".to_string(),
+                found: vec.len(),
             }),
-        ),
-        (
-            "<",
-            Func(|vec| match vec[..] {
-                [Int(a), Int(b)] => Ok(Bool(a < b)),
-                _ => Err(MalErr::E(
-                    "Wrong type of arguments for `<' operator".to_string(),
-                )),
-            }),
-        ),
-        (
-            "<=",
-            Func(|vec| match vec[..] {
-                [Int(a), Int(b)] => Ok(Bool(a <= b)),
-                _ => Err(MalErr::E(
-                    "Wrong type of arguments for `<=' operator".to_string(),
-                )),
+        }),
+        ("atom?", |vec| match vec {
+            [Atom(_)] => Ok(Bool(true)),
+            [_] => Ok(Bool(false)),
+            _ => Err(MalErr::WrongNumberOfArguments {
+                name: "atom?".to_string(),
+                expected: "1".to_string(),
+                found: vec.len(),
             }),
-        ),
-        (
-            ">",
-            Func(|vec| match vec[..] {
-                [Int(a), Int(b)] => Ok(Bool(a > b)),
-                _ => Err(MalErr::E(
-                    "Wrong type of arguments for `>' operator".to_string(),
-                )),
+        }),
+        ("deref", |vec| match vec {
+            [Atom(a)] => Ok(a.borrow().clone()),
+            _ => Err(MalErr::E("`deref' expects an atom".to_string())),
+        }),
+        ("reset!", |vec| match vec {
+            [Atom(a), val] => {
+                *a.borrow_mut() = val.clone();
+                Ok(val.clone())
+            }
+            _ => Err(MalErr::E(
+                "`reset!' expects an atom and a value".to_string(),
+            )),
+        }),
+        ("read-string", |vec| match vec {
+            [Str(s)] => read_str(s),
+            _ => Err(MalErr::E("`read-string' expects a string".to_string())),
+        }),
+        ("slurp", |vec| match vec {
+            [Str(path)] => fs::read_to_string(path)
+                .map(Str)
+                .map_err(|e| MalErr::E(format!("Unable to read {path}: {e}"))),
+            _ => Err(MalErr::E("`slurp' expects a file name".to_string())),
+        }),
+        ("throw", |vec| match vec {
+            [val] => Err(MalErr::Throw(val.clone())),
+            _ => Err(MalErr::WrongNumberOfArguments {
+                name: "throw".to_string(),
+                expected: "1".to_string(),
+                found: vec.len(),
             }),
-        ),
-        (
-            ">=",
-            Func(|vec| match vec[..] {
-                [Int(a), Int(b)] => Ok(Bool(a >= b)),
-                _ => Err(MalErr::E(
-                    "Wrong type of arguments for `>=' operator".to_string(),
-                )),
+        }),
+        // A symbol no other `gensym` call returns, for macros to bind without capturing the
+        // symbols of their caller.
+        ("gensym", |vec| {
+            let prefix = match vec {
+                [] => "G__",
+                [Str(prefix)] => prefix,
+                _ => return Err(MalErr::E("`gensym' expects an optional prefix".to_string())),
+            };
+            let n = GENSYM_COUNTER.fetch_add(1, AtomicOrdering::Relaxed);
+            Ok(MalType::Sym(format!("{prefix}{n}"), None))
+        }),
+    ];
+
+    let higher_order: Vec<(&'static str, HigherOrder)> = vec![
+        ("eval", |ctx, vec| match vec {
+            [ast] => ctx.eval(ast.clone()),
+            _ => Err(MalErr::WrongNumberOfArguments {
+                name: "eval".to_string(),
+                expected: "1".to_string(),
+                found: vec.len(),
             }),
-        ),
-        (
-            "count",
-            Func(|vec| {
-                if vec.len() < 1 {
-                    return Err(MalErr::E(
-                        "Possibly wrong number of arguments provided to `count'".to_string(),
-                    ));
+        }),
+        ("load-file", |ctx, vec| match vec {
+            [Str(path)] => {
+                let src = fs::read_to_string(path)
+                    .map_err(|e| MalErr::E(format!("Unable to read {path}: {e}")))?;
+                for form in read_source(&src, path)? {
+                    ctx.eval(form)?;
                 }
-                match &vec[0] {
-                    List(l) => Ok(Int(l.iter().count() as i32)),
-                    _ => Ok(Int(1)),
-                }
-            }),
-        ),
-        (
-            "list",
-            Func(|vec| {
+                Ok(Nil)
+            }
+            _ => Err(MalErr::E("`load-file' expects a file name".to_string())),
+        }),
+        ("apply", |ctx, vec| match vec {
+            [f, args @ .., List(last, _) | Vector(last, _)] => {
+                let mut call_args = args.to_vec();
+                call_args.extend(last.iter().cloned());
+                ctx.apply(f, call_args)
+            }
+            _ => Err(MalErr::E(
+                "`apply' expects a function, its arguments and a list of more arguments"
+                    .to_string(),
+            )),
+        }),
+        ("map", |ctx, vec| match vec {
+            [f, List(l, _) | Vector(l, _)] => {
                 let mut ret = Vec::new();
-                for each in vec {
-                    ret.push(each.clone());
+                for each in l.iter() {
+                    ret.push(ctx.apply(f, vec![each.clone()])?);
                 }
-                Ok(List(Rc::new(ret)))
-            }),
-        ),
-        (
-            "list?",
-            Func(|vec| {
-                if vec.len() < 1 {
-                    return Err(MalErr::E(
-                        "Possibly wrong number of arguments provided to `list?'".to_string(),
-                    ));
-                }
-                match &vec[0] {
-                    List(_) => Ok(Bool(true)),
-                    _ => Ok(Bool(false)),
+                Ok(List(Rc::new(ret), None))
+            }
+            _ => Err(MalErr::E(
+                "`map' expects a function and a list or vector".to_string(),
+            )),
+        }),
+        ("filter", |ctx, vec| match vec {
+            [f, List(l, _) | Vector(l, _)] => {
+                let mut ret = Vec::new();
+                for each in l.iter() {
+                    match ctx.apply(f, vec![each.clone()])? {
+                        Nil | Bool(false) => {}
+                        _ => ret.push(each.clone()),
+                    }
                 }
-            }),
-        ),
-        (
-            "empty?",
-            Func(|vec| {
-                if vec.len() < 1 {
-                    return Err(MalErr::E(
-                        "Possibly wrong number of arguments provided to `empty?'".to_string(),
-                    ));
+                Ok(List(Rc::new(ret), None))
+            }
+            _ => Err(MalErr::E(
+                "`filter' expects a function and a list or vector".to_string(),
+            )),
+        }),
+        ("reduce", |ctx, vec| {
+            let (f, init, l) = match vec {
+                [f, init, List(l, _) | Vector(l, _)] => (f, init.clone(), &l[..]),
+                [f, List(l, _) | Vector(l, _)] => match l.split_first() {
+                    Some((init, rest)) => (f, init.clone(), rest),
+                    None => return ctx.apply(f, vec![]),
+                },
+                _ => return Err(MalErr::E(
+                    "`reduce' expects a function, an optional initial value and a list or vector"
+                        .to_string(),
+                )),
+            };
+            let mut acc = init;
+            for each in l {
+                acc = ctx.apply(f, vec![acc, each.clone()])?;
+            }
+            Ok(acc)
+        }),
+        ("swap!", |ctx, vec| match vec {
+            [Atom(a), f, args @ ..] => {
+                let mut call_args = vec![a.borrow().clone()];
+                call_args.extend(args.iter().cloned());
+                let val = ctx.apply(f, call_args)?;
+                *a.borrow_mut() = val.clone();
+                Ok(val)
+            }
+            _ => Err(MalErr::E(
+                "`swap!' expects an atom, a function and its extra arguments".to_string(),
+            )),
+        }),
+        ("sort-by", |ctx, vec| match vec {
+            [f, List(l, _) | Vector(l, _)] => {
+                let mut keyed = Vec::new();
+                for each in l.iter() {
+                    keyed.push((ctx.apply(f, vec![each.clone()])?, each.clone()));
                 }
-                match &vec[0] {
-                    List(l) => Ok(Bool(l.is_empty())),
-                    _ => Ok(Bool(false)),
+                // Keys comparable with the first are of its kind and not NaN, so that every
+                // pair of them is comparable and the sort sees a total order.
+                if let Some((first, _)) = keyed.first() {
+                    for (key, _) in keyed.iter() {
+                        compare(first, key)?;
+                    }
                 }
-            }),
-        ),
-    ]
+                keyed.sort_by(|(a, _), (b, _)| {
+                    compare(a, b).expect("Keys were checked to be comparable")
+                });
+                Ok(List(
+                    Rc::new(keyed.into_iter().map(|(_, v)| v).collect()),
+                    None,
+                ))
+            }
+            _ => Err(MalErr::E(
+                "`sort-by' expects a function and a list or vector".to_string(),
+            )),
+        }),
+    ];
+
+    builtins
+        .into_iter()
+        .map(|(name, f)| (name, MalType::func(name, f)))
+        .chain(
+            higher_order
+                .into_iter()
+                .map(|(name, f)| (name, MalType::func_with_context(name, f))),
+        )
+        .collect()
 }
@@ -1,18 +1,23 @@
 use crate::core;
+use crate::types::MalErr;
 use crate::MalType;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
-#[derive(Clone)]
+/// An environment is shared between every closure created in it, so it is handed around as an
+/// `Rc<Env>` and mutated through `&self`. A `def!` after a closure was created is therefore
+/// visible from inside that closure.
 pub struct Env {
     env: RefCell<HashMap<String, MalType>>,
     pub outer: Option<Rc<Env>>,
 }
 
+/// The root environment, holding the core namespace. Every other scope is created empty with
+/// [`Env::new`] and reaches the builtins through its chain of `outer` environments.
 impl Default for Env {
     fn default() -> Self {
-        let mut env = Env {
+        let env = Env {
             env: RefCell::new(HashMap::new()),
             outer: None,
         };
@@ -26,16 +31,51 @@ impl Default for Env {
 }
 
 impl Env {
-    pub fn new(binds: Vec<MalType>, exprs: Vec<MalType>) -> Self {
-        let mut env = Env::default();
+    /// A scope binding each of `binds` to the argument in `exprs` at the same position. A `&`
+    /// in `binds` binds the symbol after it to a list of the remaining arguments. Any other
+    /// difference in the number of binds and arguments is an error.
+    pub fn new(
+        outer: Option<Rc<Env>>,
+        binds: Vec<MalType>,
+        exprs: Vec<MalType>,
+    ) -> Result<Rc<Self>, MalErr> {
+        let env = Env {
+            env: RefCell::new(HashMap::new()),
+            outer,
+        };
 
-        for (key, val) in binds.iter().zip(&exprs) {
-            env.set(&key.clone().pr_str(), val.clone());
+        let fixed = binds
+            .iter()
+            .position(|key| matches!(key, MalType::Sym(s, _) if s == "&"));
+        let arity_ok = match fixed {
+            Some(n) => exprs.len() >= n,
+            None => exprs.len() == binds.len(),
+        };
+        if !arity_ok {
+            return Err(MalErr::WrongNumberOfArguments {
+                name: "fn*".to_string(),
+                expected: match fixed {
+                    Some(n) => format!("at least {n}"),
+                    None => binds.len().to_string(),
+                },
+                found: exprs.len(),
+            });
         }
-        env
+
+        for (i, key) in binds.iter().enumerate() {
+            if Some(i) == fixed {
+                if let Some(rest) = binds.get(i + 1) {
+                    let rest_exprs = exprs[i..].to_vec();
+                    env.set(&rest.pr_str(true), MalType::List(Rc::new(rest_exprs), None));
+                }
+                break;
+            }
+            env.set(&key.pr_str(true), exprs[i].clone());
+        }
+        Ok(Rc::new(env))
     }
 
-    pub fn set(&mut self, k: &str, v: MalType) {
+    pub fn set(&self, k: &str, v: MalType) {
         self.env.borrow_mut().insert(k.to_string(), v);
     }
 
@@ -53,3 +93,43 @@ impl Env {
         self.find(k)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::env::Env;
+    use crate::eval::eval;
+    use crate::reader::read_str;
+    use crate::MalType;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn own_keys(env: &Env) -> Vec<String> {
+        let mut keys: Vec<String> = env.env.borrow().keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn scopes_chain_to_the_root() {
+        let root = Rc::new(Env::default());
+        let seen = Rc::new(RefCell::new(None));
+        let scope = seen.clone();
+        root.set(
+            "capture-scope",
+            MalType::func_with_context("capture-scope", move |ctx, _| {
+                scope.replace(Some(ctx.env().clone()));
+                Ok(MalType::Nil)
+            }),
+        );
+
+        let mal = read_str("((fn* (a) (let* (b a) (capture-scope))) 1)").unwrap();
+        eval(mal, &root).unwrap();
+
+        // Calls and `let*` scopes hold only their own bindings, the builtins stay in the root.
+        let let_env = seen.borrow_mut().take().unwrap();
+        assert_eq!(vec!["b"], own_keys(&let_env));
+        let fn_env = let_env.outer.clone().unwrap();
+        assert_eq!(vec!["a"], own_keys(&fn_env));
+        assert!(Rc::ptr_eq(&root, fn_env.outer.as_ref().unwrap()));
+    }
+}
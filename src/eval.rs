@@ -0,0 +1,1658 @@
+use crate::env::Env;
+use crate::types::Context;
+use crate::types::MalErr;
+use crate::types::MalType;
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+fn eval_ast(ast: &MalType, env: &Rc<Env>) -> Result<MalType, MalErr> {
+    match ast {
+        MalType::Sym(s, _) => match env.get(&s[..]) {
+            Some(f) => Ok(f),
+            None => Err(MalErr::SymbolNotFound(s.clone())),
+        },
+        MalType::List(l, _) => {
+            let mut vec = vec![];
+            for each in l.iter() {
+                vec.push(eval(each.clone(), env)?);
+            }
+            Ok(MalType::List(Rc::new(vec), None))
+        }
+        MalType::Vector(v, _) => {
+            let mut vec = vec![];
+            for each in v.iter() {
+                vec.push(eval(each.clone(), env)?);
+            }
+            Ok(MalType::Vector(Rc::new(vec), None))
+        }
+        MalType::HashMap(m, _) => {
+            let mut map = HashMap::new();
+            for (key, val) in m.iter() {
+                map.insert(key.clone(), eval(val.clone(), env)?);
+            }
+            Ok(MalType::HashMap(Rc::new(map), None))
+        }
+        _ => Ok(ast.clone()),
+    }
+}
+
+/// Builds the form that evaluates a quasiquoted `ast`, turning `unquote` and `splice-unquote`
+/// into calls to `cons` and `concat`.
+fn quasiquote(ast: &MalType) -> MalType {
+    match ast {
+        MalType::List(l, _) => match &l[..] {
+            [MalType::Sym(s, _), form] if s == "unquote" => form.clone(),
+            _ => quasiquote_seq(l),
+        },
+        MalType::Vector(v, _) => MalType::List(
+            Rc::new(vec![
+                MalType::Sym("vec".to_string(), None),
+                quasiquote_seq(v),
+            ]),
+            None,
+        ),
+        MalType::Sym(..) | MalType::HashMap(..) => MalType::List(
+            Rc::new(vec![MalType::Sym("quote".to_string(), None), ast.clone()]),
+            None,
+        ),
+        _ => ast.clone(),
+    }
+}
+
+fn quasiquote_seq(elts: &[MalType]) -> MalType {
+    let mut acc = MalType::List(Rc::new(vec![]), None);
+    for elt in elts.iter().rev() {
+        acc = match elt {
+            MalType::List(l, _) if matches!(&l[..], [MalType::Sym(s, _), _] if s == "splice-unquote") => {
+                MalType::List(
+                    Rc::new(vec![
+                        MalType::Sym("concat".to_string(), None),
+                        l[1].clone(),
+                        acc,
+                    ]),
+                    None,
+                )
+            }
+            _ => MalType::List(
+                Rc::new(vec![
+                    MalType::Sym("cons".to_string(), None),
+                    quasiquote(elt),
+                    acc,
+                ]),
+                None,
+            ),
+        };
+    }
+    acc
+}
+
+/// Returns the macro `ast` calls, if its head is a symbol bound to one.
+fn macro_call(ast: &MalType, env: &Rc<Env>) -> Option<MalType> {
+    match ast {
+        MalType::List(l, _) => match l.first() {
+            Some(MalType::Sym(s, _)) => match env.get(s) {
+                Some(mac @ MalType::MalFunc { is_macro: true, .. }) => Some(mac),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Expands macro calls at the head of `ast` in place until it no longer is one. On error
+/// `ast` is left at the macro call that failed. A list built by the macro takes the position
+/// of the call, so that errors in the expansion point there.
+fn macroexpand(ast: &mut MalType, env: &Rc<Env>) -> Result<(), MalErr> {
+    while let Some(mac) = macro_call(ast, env) {
+        let (head, args, span) = match &*ast {
+            MalType::List(l, span) => (l[0].clone(), l[1..].to_vec(), span.clone()),
+            _ => unreachable!("Only lists can be macro calls"),
+        };
+        *ast = match apply_as(Some(&head), &mac, args, env)? {
+            MalType::List(l, None) => MalType::List(l, span),
+            expanded => expanded,
+        };
+    }
+    Ok(())
+}
+
+/// Checks that the parameters of `fn*` are symbols, and that a `&` among them is followed by
+/// exactly one symbol, the one bound to the rest of the arguments.
+fn check_params(params: &[MalType]) -> Result<(), MalErr> {
+    if let Some(found) = params
+        .iter()
+        .find(|param| !matches!(param, MalType::Sym(..)))
+    {
+        return Err(MalErr::WrongType {
+            expected: "symbol in `fn*' parameters".to_string(),
+            found: found.clone(),
+        });
+    }
+    let rest = match params
+        .iter()
+        .position(|param| matches!(param, MalType::Sym(s, _) if s == "&"))
+    {
+        Some(i) => &params[i + 1..],
+        None => return Ok(()),
+    };
+    match rest {
+        [MalType::Sym(s, _)] if s != "&" => Ok(()),
+        _ => Err(MalErr::WrongType {
+            expected: "one symbol after `&' in `fn*' parameters".to_string(),
+            found: MalType::List(Rc::new(params.to_vec()), None),
+        }),
+    }
+}
+
+/// Names the function in an arity error from [`Env::new`] after `head`, the form it was
+/// called as.
+fn called_as(err: MalErr, head: &MalType) -> MalErr {
+    match err {
+        MalErr::WrongNumberOfArguments {
+            expected, found, ..
+        } => MalErr::WrongNumberOfArguments {
+            name: head.pr_str(true),
+            expected,
+            found,
+        },
+        err => err,
+    }
+}
+
+/// The `fn*` form a function was made from, to name it by when it was not called by name.
+fn fn_form(params: &[MalType], body: &MalType) -> MalType {
+    MalType::List(
+        Rc::new(vec![
+            MalType::Sym("fn*".to_string(), None),
+            MalType::List(Rc::new(params.to_vec()), None),
+            body.clone(),
+        ]),
+        None,
+    )
+}
+
+/// Calls `f` with already evaluated `args`, outside of any tail position. `env` is the
+/// environment of the caller, handed to native functions through their [`Context`].
+pub(crate) fn apply(f: &MalType, args: Vec<MalType>, env: &Rc<Env>) -> Result<MalType, MalErr> {
+    apply_as(None, f, args, env)
+}
+
+/// Calls `f` like [`apply`], naming it after `head` in arity errors, the form it was called
+/// as. Without one it is named after its `fn*` form.
+pub(crate) fn apply_as(
+    head: Option<&MalType>,
+    f: &MalType,
+    args: Vec<MalType>,
+    env: &Rc<Env>,
+) -> Result<MalType, MalErr> {
+    match f {
+        MalType::Func { f, .. } => f(&Context::new(env), &args),
+        MalType::MalFunc {
+            env, params, body, ..
+        } => {
+            let env =
+                Env::new(Some(env.clone()), params.clone(), args).map_err(|e| match head {
+                    Some(head) => called_as(e, head),
+                    None => called_as(e, &fn_form(params, body)),
+                })?;
+            eval(body.as_ref().clone(), &env)
+        }
+        _ => Err(MalErr::WrongType {
+            expected: "function".to_string(),
+            found: f.clone(),
+        }),
+    }
+}
+
+pub(crate) fn eval(mut ast: MalType, env: &Rc<Env>) -> Result<MalType, MalErr> {
+    eval_form(&mut ast, env).map_err(|e| e.at(ast.span()))
+}
+
+/// Evaluates `ast` in place: tail calls and macro expansion replace it, so that on error it
+/// is the form that failed. Inlined so that a level of recursion stays a single frame.
+#[inline(always)]
+fn eval_form(ast: &mut MalType, env: &Rc<Env>) -> Result<MalType, MalErr> {
+    // Tail calls replace `ast` and `env` and go around the loop again instead of recursing.
+    let mut env = env.clone();
+
+    loop {
+        macroexpand(ast, &env)?;
+        let l = match ast.clone() {
+            MalType::List(l, _) if l.is_empty() => return Ok(ast.clone()),
+            MalType::List(l, _) => l,
+            _ => return eval_ast(ast, &env),
+        };
+
+        match &l[..] {
+            [MalType::Sym(s, _), MalType::Sym(x, _), y] if s == "def!" => {
+                let evaluated = eval(y.clone(), &env)?;
+                env.set(x, evaluated.clone());
+                return Ok(evaluated);
+            }
+            [MalType::Sym(s, _), MalType::List(l, _) | MalType::Vector(l, _), y] if s == "let*" => {
+                if !l.len().is_multiple_of(2) {
+                    return Err(MalErr::E(format!(
+                        "`let*' expects pairs of bindings, found {} forms",
+                        l.len()
+                    )));
+                }
+                let new_env = Env::new(Some(env.clone()), vec![], vec![])?;
+                for (key, val) in l.iter().cloned().tuples() {
+                    match key {
+                        MalType::Sym(s, _) => {
+                            let evaluated = eval(val.clone(), &new_env)?;
+                            new_env.set(&s, evaluated);
+                        }
+                        found => {
+                            return Err(MalErr::WrongType {
+                                expected: "symbol in `let*' bindings".to_string(),
+                                found,
+                            })
+                        }
+                    }
+                }
+                *ast = y.clone();
+                env = new_env;
+            }
+            [MalType::Sym(s, _), body @ ..] if s == "do" => match body.split_last() {
+                Some((last, init)) => {
+                    for each in init {
+                        eval(each.clone(), &env)?;
+                    }
+                    *ast = last.clone();
+                }
+                None => return Ok(MalType::Nil),
+            },
+            [MalType::Sym(s, _), args @ ..] if s == "if" => {
+                if !(2..=3).contains(&args.len()) {
+                    return Err(MalErr::WrongNumberOfArguments {
+                        name: s.clone(),
+                        expected: "2 or 3".to_string(),
+                        found: args.len(),
+                    });
+                }
+                match eval(args[0].clone(), &env)? {
+                    MalType::Nil | MalType::Bool(false) => match args.get(2) {
+                        Some(otherwise) => *ast = otherwise.clone(),
+                        None => return Ok(MalType::Nil),
+                    },
+                    _ => *ast = args[1].clone(),
+                }
+            }
+            [MalType::Sym(s, _), MalType::List(params, _) | MalType::Vector(params, _), body]
+                if s == "fn*" =>
+            {
+                check_params(params)?;
+                return Ok(MalType::MalFunc {
+                    env: env.clone(),
+                    params: params.clone().to_vec(),
+                    body: Rc::new(body.clone()),
+                    is_macro: false,
+                });
+            }
+            [MalType::Sym(s, _), MalType::Sym(x, _), y] if s == "defmacro!" => {
+                let mac = match eval(y.clone(), &env)? {
+                    MalType::MalFunc {
+                        env, params, body, ..
+                    } => MalType::MalFunc {
+                        env,
+                        params,
+                        body,
+                        is_macro: true,
+                    },
+                    found => {
+                        return Err(MalErr::WrongType {
+                            expected: "function as the body of `defmacro!'".to_string(),
+                            found,
+                        })
+                    }
+                };
+                env.set(x, mac.clone());
+                return Ok(mac);
+            }
+            [MalType::Sym(s, _), form] if s == "macroexpand" => {
+                let mut form = form.clone();
+                macroexpand(&mut form, &env)?;
+                return Ok(form);
+            }
+            [MalType::Sym(s, _), form] if s == "quote" => return Ok(form.clone()),
+            [MalType::Sym(s, _), form] if s == "quasiquoteexpand" => return Ok(quasiquote(form)),
+            [MalType::Sym(s, _), form] if s == "quasiquote" => *ast = quasiquote(form),
+            [MalType::Sym(s, _), args @ ..]
+                if s == "quote" || s == "quasiquoteexpand" || s == "quasiquote" =>
+            {
+                return Err(MalErr::WrongNumberOfArguments {
+                    name: s.clone(),
+                    expected: "1".to_string(),
+                    found: args.len(),
+                })
+            }
+            [MalType::Sym(s, _), expr] if s == "try*" => *ast = expr.clone(),
+            [MalType::Sym(s, _), expr, catch] if s == "try*" => {
+                let (bind, handler) = match catch {
+                    MalType::List(l, _) if matches!(&l[..], [MalType::Sym(c, _), MalType::Sym(..), _] if c == "catch*") => {
+                        (&l[1], &l[2])
+                    }
+                    _ => {
+                        return Err(MalErr::WrongType {
+                            expected: "(catch* symbol body) in `try*'".to_string(),
+                            found: catch.clone(),
+                        })
+                    }
+                };
+                let exception = match eval(expr.clone(), &env).map_err(MalErr::into_inner) {
+                    Ok(val) => return Ok(val),
+                    Err(MalErr::Throw(val)) => val,
+                    Err(e) => MalType::Str(e.to_string()),
+                };
+                env = Env::new(Some(env.clone()), vec![bind.clone()], vec![exception])?;
+                *ast = handler.clone();
+            }
+            [MalType::Sym(s, _), args @ ..] if s == "try*" => {
+                return Err(MalErr::WrongNumberOfArguments {
+                    name: s.clone(),
+                    expected: "1 or 2".to_string(),
+                    found: args.len(),
+                })
+            }
+            [MalType::Sym(s, _), args @ ..] if s == "macroexpand" => {
+                return Err(MalErr::WrongNumberOfArguments {
+                    name: s.clone(),
+                    expected: "1".to_string(),
+                    found: args.len(),
+                })
+            }
+            [MalType::Sym(s, _), args @ ..]
+                if s == "def!" || s == "defmacro!" || s == "let*" || s == "fn*" =>
+            {
+                if args.len() != 2 {
+                    return Err(MalErr::WrongNumberOfArguments {
+                        name: s.clone(),
+                        expected: "2".to_string(),
+                        found: args.len(),
+                    });
+                }
+                let expected = match &s[..] {
+                    "def!" => "symbol as the name in `def!'",
+                    "defmacro!" => "symbol as the name in `defmacro!'",
+                    "let*" => "list of bindings in `let*'",
+                    _ => "list of parameters in `fn*'",
+                };
+                return Err(MalErr::WrongType {
+                    expected: expected.to_string(),
+                    found: args[0].clone(),
+                });
+            }
+            _ => match eval_ast(ast, &env)? {
+                MalType::List(ref evaluated, _) => match &evaluated[..] {
+                    [MalType::Func { f, .. }, args @ ..] => return f(&Context::new(&env), args),
+                    [MalType::MalFunc {
+                        env: fn_env,
+                        params,
+                        body,
+                        ..
+                    }, args @ ..] => {
+                        env = Env::new(Some(fn_env.clone()), params.to_vec(), args.to_vec())
+                            .map_err(|e| called_as(e, &l[0]))?;
+                        *ast = body.as_ref().clone();
+                    }
+                    [head, ..] => {
+                        return Err(MalErr::WrongType {
+                            expected: "function".to_string(),
+                            found: head.clone(),
+                        })
+                    }
+                    [] => unreachable!("Empty lists evaluate to themselves"),
+                },
+                _ => unreachable!("Evaluating a list always gives back a list"),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::env::Env;
+    use crate::eval::eval;
+    use crate::interpreter::root_env;
+    use crate::reader::{read_all, read_source, read_str};
+    use crate::types::{MalErr, MalType};
+    use crate::Interpreter;
+    use itertools::Itertools;
+    use std::cell::Cell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    #[test]
+    fn step1() {
+        let hash = HashMap::from([
+            ("()", "()"),
+            ("1", "1"),
+            ("    1", "1"),
+            ("    -123    ", "-123"),
+            ("+", "+"),
+            ("    abc", "abc"),
+            ("    abc123    ", "abc123"),
+            ("abc-def", "abc-def"),
+            ("( * 1   2   )", "(* 1 2)"),
+            ("(1, 2, 3,,,,),,,", "(1 2 3)"),
+            ("  ( +   1 (+  2 3  )  )", "(+ 1 (+ 2 3))"),
+            ("(def! x 3)", "(def! x 3)"),
+            ("(1 2 3 4 5 6)", "(1 2 3 4 5 6)"),
+        ]);
+
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, mal.pr_str(true));
+        }
+    }
+
+    #[test]
+    fn read_all_forms() {
+        let forms = read_all("(def! a 1) (def! b 2)\n; comment\n[a b] ").unwrap();
+        let printed: Vec<String> = forms.iter().map(|f| f.pr_str(true)).collect();
+        assert_eq!(vec!["(def! a 1)", "(def! b 2)", "[a b]"], printed);
+        assert!(read_all("").unwrap().is_empty());
+        assert!(read_all("  ; only a comment").unwrap().is_empty());
+
+        for input in ["(+ 1 2))", "1 2", "(def! a 1) (def! b 2)", ")", "]", "(1 }"] {
+            assert!(
+                matches!(read_str(input), Err(MalErr::ParseErr(_))),
+                "{input}"
+            );
+        }
+        assert!(read_all("(1 2) )").is_err());
+        assert_eq!("3", read_str(" 3  ; three\n").unwrap().pr_str(true));
+
+        let interp = Interpreter::new();
+        let last = interp
+            .eval_all("(def! a 1) (def! b (+ a 1)) (list a b)")
+            .unwrap();
+        assert_eq!("(1 2)", last.pr_str(true));
+        assert_eq!("nil", interp.eval_all("").unwrap().pr_str(true));
+    }
+
+    #[test]
+    fn source_positions() {
+        let err = read_source("(def! a 1)\n(do\n\t(list 1", "parse.mal").unwrap_err();
+        assert_eq!(
+            "parse.mal:3:2: Expected ) but found EOF\n\t(list 1\n\t^",
+            err.to_string()
+        );
+        let err = read_source("(list 1))", "close.mal").unwrap_err();
+        assert_eq!(
+            "close.mal:1:9: Parse error: Unexpected )\n(list 1))\n        ^",
+            err.to_string()
+        );
+        assert!(matches!(err.into_inner(), MalErr::ParseErr(_)));
+
+        let interp = Interpreter::new();
+        let src = "(def! f (fn* (x)\n  (+ x (g x))))\n\n(f 1)";
+        let err = interp.eval_source(src, "eval.mal").unwrap_err();
+        assert_eq!(
+            "eval.mal:2:9: Unable to find `g' in current environment\n  (+ x (g x))))\n        ^",
+            err.to_string()
+        );
+        assert!(matches!(err.into_inner(), MalErr::SymbolNotFound(_)));
+        let err = interp
+            .eval_source("(+ 1 undefined-sym)", "sym.mal")
+            .unwrap_err();
+        assert_eq!(
+            "sym.mal:1:6: Unable to find `undefined-sym' in current environment\n(+ 1 undefined-sym)\n     ^",
+            err.to_string()
+        );
+        // Other errors point at the opening bracket of the form that raised them, whatever
+        // its head is.
+        let err = interp
+            .eval_source("(list\n  (+ 1 \"a\"))", "call.mal")
+            .unwrap_err();
+        assert!(err.to_string().starts_with("call.mal:2:3: "), "{err}");
+        let err = interp
+            .eval_source("(def! a 1)\n (1 2 3)", "head.mal")
+            .unwrap_err();
+        assert_eq!(
+            "head.mal:2:2: Expected function but found 1\n (1 2 3)\n ^",
+            err.to_string()
+        );
+        let err = interp
+            .eval_source("((fn* (x) x))", "arity.mal")
+            .unwrap_err();
+        assert!(err.to_string().starts_with("arity.mal:1:1: "), "{err}");
+        let err = interp
+            .eval_source("(list {:a (+ 1 \"a\")})", "map.mal")
+            .unwrap_err();
+        assert!(err.to_string().starts_with("map.mal:1:11: "), "{err}");
+
+        // Forms built at runtime keep the positions of the parts that were read, and a macro
+        // expansion that of the call.
+        let err = interp
+            .eval_source("(def! m 1)\n(eval (list 'h))", "built.mal")
+            .unwrap_err();
+        assert!(err.to_string().starts_with("built.mal:2:14: "), "{err}");
+        let err = interp
+            .eval_source("(def! m 1)\n(eval (read-string \"(h)\"))", "built.mal")
+            .unwrap_err();
+        assert!(err.to_string().starts_with("built.mal:2:1: "), "{err}");
+        let err = interp
+            .eval_source("(defmacro! bad (fn* () (list 1 2)))\n  (bad)", "macro.mal")
+            .unwrap_err();
+        assert!(err.to_string().starts_with("macro.mal:2:3: "), "{err}");
+
+        let val = interp
+            .eval_source("(try* (f 1) (catch* e e))", "caught.mal")
+            .unwrap();
+        assert_eq!(
+            "\"Unable to find `g' in current environment\"",
+            val.pr_str(true)
+        );
+        let val = interp
+            .eval_source("(try*\n  (throw {:a 1})\n  (catch* e e))", "thrown.mal")
+            .unwrap();
+        assert_eq!("{:a 1}", val.pr_str(true));
+
+        // Forms read without a source carry no position.
+        let err = interp.eval_all("(h 1)").unwrap_err();
+        assert!(matches!(err, MalErr::SymbolNotFound(_)));
+    }
+
+    #[test]
+    fn step2() {
+        let hash = HashMap::from([
+            ("(+ 1 2)", "3"),
+            ("(+ 5 (* 2 3))", "11"),
+            ("(- (+ 5 (* 2 3)) 3)", "8"),
+            ("(/ (- (+ 5 (* 2 3)) 3) 4)", "2"),
+            ("(/ (- (+ 515 (* 87 311)) 302) 27)", "1010"),
+            ("(* -3 6)", "-18"),
+            ("(/ (- (+ 515 (* -87 311)) 296) 27)", "-994"),
+        ]);
+        let env = Rc::new(Env::default());
+
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap().pr_str(true));
+        }
+    }
+
+    #[test]
+    fn step3() {
+        let hash = HashMap::from([
+            ("(def! x 3)", "3"),
+            ("(def! x (+ 1 7))", "8"),
+            ("(def! y (let* (z 7) z))", "7"),
+            ("(let* (z 9) z)", "9"),
+            ("(let* (z (+ 2 3)) (+ 1 z))", "6"),
+            ("(let* (p (+ 2 3) q (+ 2 p)) (+ p q))", "12"),
+            ("(let* (x 2 x 3) x)", "3"),
+        ]);
+        let env = Rc::new(Env::default());
+
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap().pr_str(true));
+        }
+
+        let mal = read_str("(def! a 4)").unwrap();
+        assert_eq!("4", eval(mal, &env).unwrap().pr_str(true));
+
+        let mal = read_str("(let* (z 2) (let* (q 9) a))").unwrap();
+        assert_eq!("4", eval(mal, &env).unwrap().pr_str(true));
+
+        /*
+        Hashmaps donot store items in the order of their insertion and so they are not
+        accessed in order in the for loop, the order is arbitrary. So this makes it very
+        diffucult to test the values are added to the environment properly and so had to
+        write the test one after the other.
+        */
+    }
+
+    #[test]
+    fn step4() {
+        let hash = HashMap::from([
+            ("(do (def! a 6) 7 (+ a 8))", "14"),
+            ("(def! DO 7)", "7"),
+            ("(if true 7 8)", "7"),
+            ("(if false 7 8)", "8"),
+            ("(if false 7)", "nil"),
+            ("(if false 7 false)", "false"),
+            ("(if true (+ 1 7) (+ 1 8))", "8"),
+            ("(if false (+ 1 7) (+ 1 8))", "9"),
+            ("(if nil 7 8)", "8"),
+            ("(if 0 7 8)", "7"),
+            ("(if false (+ 1 7))", "nil"),
+            ("(if nil 8)", "nil"),
+            ("(if nil 8 7)", "7"),
+            ("(if true (+ 1 7))", "8"),
+            ("(fn* (a) a)", "<user:fn>"),
+            ("( (fn* () 4) )", "4"),
+            ("( (fn* (a) a) 7)", "7"),
+            ("( (fn* (a) (+ a 1)) 10)", "11"),
+            ("( (fn* (a b) (+ b a)) 3 4)", "7"),
+            ("( (fn* (a b) (+ a b)) 2 3)", "5"),
+            ("( (fn* (f x) (f x)) (fn* (a) (+ 1 a)) 7)", "8"),
+            ("( ( (fn* (a) (fn* (b) (+ a b))) 5) 7)", "12"),
+        ]);
+        let env = Rc::new(Env::default());
+
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap().pr_str(true));
+        }
+    }
+
+    #[test]
+    fn step4_ex1() {
+        let env = Rc::new(Env::default());
+        let mal = read_str("(def! gen-plus5 (fn* () (fn* (b) (+ 5 b))))").unwrap();
+        eval(mal, &env).unwrap();
+        let mal = read_str("(def! plus5 (gen-plus5))").unwrap();
+        eval(mal, &env).unwrap();
+        let mal = read_str("(plus5 7)").unwrap();
+        assert_eq!("12", eval(mal, &env).unwrap().pr_str(true));
+    }
+
+    #[test]
+    fn step4_closure_sees_later_def() {
+        let env = Rc::new(Env::default());
+        let mal = read_str("(def! get-later (fn* () later))").unwrap();
+        eval(mal, &env).unwrap();
+        let mal = read_str("(def! later 5)").unwrap();
+        eval(mal, &env).unwrap();
+        let mal = read_str("(get-later)").unwrap();
+        assert_eq!("5", eval(mal, &env).unwrap().pr_str(true));
+
+        let mal = read_str("(def! later 6)").unwrap();
+        eval(mal, &env).unwrap();
+        let mal = read_str("(get-later)").unwrap();
+        assert_eq!("6", eval(mal, &env).unwrap().pr_str(true));
+    }
+
+    #[test]
+    fn variadic() {
+        let hash = HashMap::from([
+            ("(+)", "0"),
+            ("(+ 5)", "5"),
+            ("(+ 1 2 3 4)", "10"),
+            ("(*)", "1"),
+            ("(* 2 3 4)", "24"),
+            ("(- 5)", "-5"),
+            ("(- 10 1 2 3)", "4"),
+            ("(/ 2)", "1/2"),
+            ("(/ 100 5 2)", "10"),
+            ("(< 1)", "true"),
+            ("(< 1 2 3)", "true"),
+            ("(< 1 3 2)", "false"),
+            ("(<= 1 1 2)", "true"),
+            ("(> 3 2 1)", "true"),
+            ("(>= 3 3 4)", "false"),
+            ("(apply + (list 1 2 3))", "6"),
+            ("((fn* (a & rest) (list a rest)) 1)", "(1 ())"),
+            ("((fn* (a b & rest) rest) 1 2 3 4)", "(3 4)"),
+            ("((fn* [& xs] (count xs)) 1 2 3)", "3"),
+        ]);
+        let env = Rc::new(Env::default());
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap().pr_str(true), "{input}");
+        }
+
+        let hash = HashMap::from([
+            (
+                "(-)",
+                "Wrong number of arguments to `-': expected at least 1, found 0",
+            ),
+            (
+                "(/)",
+                "Wrong number of arguments to `/': expected at least 1, found 0",
+            ),
+            (
+                "(<)",
+                "Wrong number of arguments to `<': expected at least 1, found 0",
+            ),
+            (
+                "(+ 1 2 \"3\")",
+                "Wrong number or type of arguments provided to operator `+'",
+            ),
+            (
+                "((fn* (a b) a) 1)",
+                "Wrong number of arguments to `(fn* (a b) a)': expected 2, found 1",
+            ),
+            (
+                "((fn* (a) a) 1 2)",
+                "Wrong number of arguments to `(fn* (a) a)': expected 1, found 2",
+            ),
+            (
+                "((fn* (a b & c) a) 1)",
+                "Wrong number of arguments to `(fn* (a b & c) a)': expected at least 2, found 1",
+            ),
+            (
+                "(do (def! pair (fn* (a b) [a b])) (pair 1))",
+                "Wrong number of arguments to `pair': expected 2, found 1",
+            ),
+            (
+                "(map (fn* (a b) a) [1])",
+                "Wrong number of arguments to `(fn* (a b) a)': expected 2, found 1",
+            ),
+            (
+                "(swap! (atom 1) (fn* () 2))",
+                "Wrong number of arguments to `(fn* () 2)': expected 0, found 1",
+            ),
+            (
+                "(apply (fn* (a b) [a b]) [1 2 3])",
+                "Wrong number of arguments to `(fn* (a b) [a b])': expected 2, found 3",
+            ),
+            (
+                "(do (defmacro! m (fn* (a) a)) (m))",
+                "Wrong number of arguments to `m': expected 1, found 0",
+            ),
+            (
+                "(fn* (1 2) 3)",
+                "Expected symbol in `fn*' parameters but found 1",
+            ),
+            (
+                "((fn* (a \"b\") 3) 5 6)",
+                "Expected symbol in `fn*' parameters but found \"b\"",
+            ),
+            (
+                "(fn* [a & [r]] r)",
+                "Expected symbol in `fn*' parameters but found [r]",
+            ),
+            (
+                "(fn* (a &) a)",
+                "Expected one symbol after `&' in `fn*' parameters but found (a &)",
+            ),
+            (
+                "(fn* (& r extra) r)",
+                "Expected one symbol after `&' in `fn*' parameters but found (& r extra)",
+            ),
+            (
+                "(fn* [a & & r] r)",
+                "Expected one symbol after `&' in `fn*' parameters but found (a & & r)",
+            ),
+        ]);
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap_err().to_string(), "{input}");
+        }
+    }
+
+    #[test]
+    fn integer_arithmetic() {
+        let hash = HashMap::from([
+            ("(* 4294967296 2)", "8589934592"),
+            ("(- 9223372036854775807)", "-9223372036854775807"),
+            ("(quot 7 2)", "3"),
+            ("(quot -7 2)", "-3"),
+            ("(rem 7 2)", "1"),
+            ("(rem -7 2)", "-1"),
+            ("(mod -7 2)", "1"),
+            ("(mod 7 -2)", "-1"),
+            ("(mod 6 -2)", "0"),
+            ("(abs -3)", "3"),
+            ("(min 3 1 2)", "1"),
+            ("(max 3)", "3"),
+            ("(max 1 3 2)", "3"),
+            ("(pow 2 62)", "4611686018427387904"),
+            ("(pow 5 0)", "1"),
+            ("(try* (/ 1 0) (catch* e e))", "\"Division by zero\""),
+        ]);
+        let env = Rc::new(Env::default());
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap().pr_str(true), "{input}");
+        }
+
+        let hash = HashMap::from([
+            ("(/ 0)", "Division by zero"),
+            ("(mod 1 0)", "Division by zero"),
+            ("(rem 1 0)", "Division by zero"),
+            ("(quot 1 0)", "Division by zero"),
+            (
+                "(min)",
+                "Wrong number of arguments to `min': expected at least 1, found 0",
+            ),
+            (
+                "(mod 1)",
+                "Wrong number of arguments to `mod': expected 2, found 1",
+            ),
+        ]);
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap_err().to_string(), "{input}");
+        }
+    }
+
+    #[test]
+    fn bignums_and_ratios() {
+        let hash = HashMap::from([
+            ("99999999999999999999", "99999999999999999999"),
+            ("-99999999999999999999", "-99999999999999999999"),
+            ("22/7", "22/7"),
+            ("-4/6", "-2/3"),
+            ("4/2", "2"),
+            ("'(1/x 1/-2 /2)", "(1/x 1/-2 /2)"),
+            ("(+ 9223372036854775807 1)", "9223372036854775808"),
+            ("(- -9223372036854775807 2)", "-9223372036854775809"),
+            ("(* 9223372036854775807 2)", "18446744073709551614"),
+            ("(/ -9223372036854775808 -1)", "9223372036854775808"),
+            ("(abs -9223372036854775808)", "9223372036854775808"),
+            ("(- (+ 9223372036854775807 1) 1)", "9223372036854775807"),
+            ("(pow 2 100)", "1267650600228229401496703205376"),
+            ("(= (pow 2 1048576) (* (pow 2 1048575) 2))", "true"),
+            ("(pow 1 2000000000)", "1"),
+            ("(pow -1 2000000001)", "-1"),
+            ("(pow 2 -2)", "1/4"),
+            ("(pow 2/3 2)", "4/9"),
+            ("(pow 4 1/2)", "2.0"),
+            ("(/ 1 3)", "1/3"),
+            ("(/ 10 4)", "5/2"),
+            ("(/ 3)", "1/3"),
+            ("(+ 1/3 2/3)", "1"),
+            ("(+ 1/3 1/6)", "1/2"),
+            ("(* 1/3 3)", "1"),
+            ("(- 1/2)", "-1/2"),
+            ("(+ 1/2 0.25)", "0.75"),
+            ("(+ 99999999999999999999 0.5)", "1e20"),
+            ("(quot 7/2 1/3)", "10"),
+            ("(rem 7/2 1/3)", "1/6"),
+            ("(mod -7/2 1)", "1/2"),
+            ("(mod 99999999999999999999 7)", "1"),
+            ("(< 1/3 0.34 1/2 1 99999999999999999999)", "true"),
+            ("(= 1/3 (/ 2 6))", "true"),
+            (
+                "(= 99999999999999999999 (* 99999999999999999999 1))",
+                "true",
+            ),
+            ("(min 1/2 1/3)", "1/3"),
+            ("(abs -1/2)", "1/2"),
+            ("(floor -7/2)", "-4"),
+            ("(ceil 7/2)", "4"),
+            ("(round 5/2)", "3"),
+            ("(round -5/2)", "-3"),
+            ("(int 7/2)", "3"),
+            ("(int -7/2)", "-3"),
+            ("(int 1e20)", "100000000000000000000"),
+            ("(float 1/4)", "0.25"),
+            ("(float 99999999999999999999)", "1e20"),
+            ("(sqrt 1/4)", "0.5"),
+            ("(read-string (pr-str (/ 22 7)))", "22/7"),
+        ]);
+        let env = Rc::new(Env::default());
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap().pr_str(true), "{input}");
+        }
+
+        let hash = HashMap::from([
+            ("(/ 1/2 0)", "Division by zero"),
+            ("(mod 99999999999999999999 0)", "Division by zero"),
+            ("(pow 0 -1)", "Division by zero"),
+            (
+                "(pow 7 3000000)",
+                "Result of `pow' too large: 7 to the power of 3000000",
+            ),
+            (
+                "(pow 10 2000000000)",
+                "Result of `pow' too large: 10 to the power of 2000000000",
+            ),
+            (
+                "(pow 1/3 -2000000)",
+                "Result of `pow' too large: 1/3 to the power of -2000000",
+            ),
+            (
+                "(pow 2 99999999999999999999)",
+                "Exponent too large: 99999999999999999999",
+            ),
+        ]);
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap_err().to_string(), "{input}");
+        }
+    }
+
+    #[test]
+    fn floats() {
+        let hash = HashMap::from([
+            ("1.5", "1.5"),
+            ("-0.25", "-0.25"),
+            ("1e3", "1000.0"),
+            ("2.5E-3", "0.0025"),
+            ("1.", "1.0"),
+            ("1e300", "1e300"),
+            ("1e-7", "1e-7"),
+            ("0.1", "0.1"),
+            ("##Inf", "##Inf"),
+            ("'(##-Inf ##NaN)", "(##-Inf ##NaN)"),
+            ("'(1e 1.5.2 -.5 e3)", "(1e 1.5.2 -.5 e3)"),
+            ("(+ 0.1 0.2)", "0.30000000000000004"),
+            ("(+ 1 2.0)", "3.0"),
+            ("(* 2 1.5 2)", "6.0"),
+            ("(- 1.5)", "-1.5"),
+            ("(/ 2.0)", "0.5"),
+            ("(/ 1 2.0)", "0.5"),
+            ("(/ 1.0 0)", "##Inf"),
+            ("(< 1 1.5 2)", "true"),
+            ("(< 1 ##NaN)", "false"),
+            ("(>= 2.0 2)", "true"),
+            ("(= 1.5 1.5)", "true"),
+            ("(min 2 1.5 3)", "1.5"),
+            ("(max 2 1.5)", "2"),
+            ("(abs -1.5)", "1.5"),
+            ("(quot 7.5 2)", "3.0"),
+            ("(rem -7.5 2)", "-1.5"),
+            ("(mod -7.5 2)", "0.5"),
+            ("(pow 2 0.5)", "1.4142135623730951"),
+            ("(pow 2.0 -1)", "0.5"),
+            ("(pow 2 10)", "1024"),
+            ("(floor -1.5)", "-2.0"),
+            ("(ceil 1.2)", "2.0"),
+            ("(round 2.5)", "3.0"),
+            ("(round -2.5)", "-3.0"),
+            ("(round 3)", "3"),
+            ("(sqrt 16)", "4.0"),
+            ("(int -2.9)", "-2"),
+            ("(int 7)", "7"),
+            ("(float 7)", "7.0"),
+            ("(sort-by (fn* (x) x) [3 1.5 2])", "(1.5 2 3)"),
+            ("(< 9007199254740992.0 9007199254740993)", "true"),
+            ("(= 9007199254740993 9007199254740992.0)", "false"),
+            ("(<= 9007199254740992 9007199254740992.0)", "true"),
+            ("(> 1/3 0.3333333333333333)", "true"),
+            ("(< (pow 10 400) ##Inf)", "true"),
+            ("(> (pow 10 400) ##-Inf 1e308)", "false"),
+            ("(< ##-Inf (- (pow 10 400)))", "true"),
+            (
+                "(max 9007199254740993 9007199254740992.0)",
+                "9007199254740993",
+            ),
+            (
+                "(read-string (pr-str 0.30000000000000004))",
+                "0.30000000000000004",
+            ),
+        ]);
+        let env = Rc::new(Env::default());
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap().pr_str(true), "{input}");
+        }
+
+        // Integers past 2^53 and the floats between them sort by their exact values, in
+        // whatever order they come.
+        let keys = [
+            "9007199254740991",
+            "9007199254740992",
+            "9007199254740993",
+            "9007199254740994",
+            "9007199254740995",
+            "9007199254740992.0",
+            "9007199254740994.0",
+        ];
+        let mut seed = 1u64;
+        for _ in 0..30 {
+            let shuffled = (0..40)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    keys[(seed >> 33) as usize % keys.len()]
+                })
+                .join(" ");
+            let mal = read_str(&format!("(sort-by (fn* (x) x) [{shuffled}])")).unwrap();
+            let sorted = match eval(mal, &env).unwrap() {
+                MalType::List(l, _) => l,
+                val => panic!("Expected a list but found {}", val.pr_str(true)),
+            };
+            let cmp = |a: &MalType, b: &MalType| {
+                let mal = read_str(&format!("(<= {} {})", a.pr_str(true), b.pr_str(true)));
+                eval(mal.unwrap(), &env).unwrap()
+            };
+            for pair in sorted.windows(2) {
+                assert_eq!(MalType::Bool(true), cmp(&pair[0], &pair[1]), "{shuffled}");
+            }
+        }
+
+        let hash = HashMap::from([
+            ("(int ##NaN)", "Cannot convert ##NaN to an integer"),
+            ("(int ##-Inf)", "Cannot convert ##-Inf to an integer"),
+            (
+                "(floor \"1.5\")",
+                "Wrong number or type of arguments provided to operator `floor'",
+            ),
+            (
+                "(pow \"2\" -1)",
+                "Wrong number or type of arguments provided to operator `pow'",
+            ),
+            (
+                "(sqrt 1 2)",
+                "Wrong number of arguments to `sqrt': expected 1, found 2",
+            ),
+        ]);
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap_err().to_string(), "{input}");
+        }
+    }
+
+    #[test]
+    fn equality() {
+        let env = root_env();
+        let forms = [
+            ("(def! f (fn* (x) x))", "<user:fn>"),
+            ("(def! a (atom 1))", "(atom 1)"),
+            ("(= 1)", "true"),
+            ("(= 1 1 1)", "true"),
+            ("(= 1 1 2)", "false"),
+            ("(= nil false)", "false"),
+            ("(= 1 \"1\")", "false"),
+            ("(= 1 1.0)", "false"),
+            ("(= 1/2 0.5)", "false"),
+            ("(= ##NaN ##NaN)", "false"),
+            ("(= :a :a)", "true"),
+            ("(= :a \"a\")", "false"),
+            ("(= 'a 'a)", "true"),
+            ("(= 'a \"a\")", "false"),
+            ("(= '(1 (2 [3])) (list 1 (list 2 [3])))", "true"),
+            ("(= '(1 2) '(1 2 3))", "false"),
+            ("(= [1 2] '(1 2))", "true"),
+            ("(= [] '())", "true"),
+            ("(= [] nil)", "false"),
+            (
+                "(= {:a [1 {\"b\" 2}]} (hash-map :a '(1 {\"b\" 2})))",
+                "true",
+            ),
+            ("(= {:a 1} {:a 2})", "false"),
+            ("(= {:a 1} {\"a\" 1})", "false"),
+            ("(= f f)", "true"),
+            ("(= f (fn* (x) x))", "false"),
+            ("(= + +)", "true"),
+            ("(= + -)", "false"),
+            ("(= a a)", "true"),
+            ("(= a (atom 1))", "false"),
+            ("(= [a f +] (list a f +))", "true"),
+        ];
+        for (input, output) in forms {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap().pr_str(true), "{input}");
+        }
+
+        let mal = read_str("(=)").unwrap();
+        assert_eq!(
+            "Wrong number of arguments to `=': expected at least 1, found 0",
+            eval(mal, &env).unwrap_err().to_string()
+        );
+
+        let list = read_str("(1 [2 :three])").unwrap();
+        assert_eq!(list, read_str("[1 (2 :three)]").unwrap());
+        assert_ne!(list, read_str("(1 [2 \"three\"])").unwrap());
+    }
+
+    #[test]
+    fn step4_reccursive_fn() {
+        let env = Rc::new(Env::default());
+        let mal =
+            read_str("(def! sumdown (fn* (N) (if (> N 0) (+ N (sumdown  (- N 1))) 0)))").unwrap();
+        eval(mal, &env).unwrap();
+        let mal = read_str("(sumdown 6)").unwrap();
+        assert_eq!("21", eval(mal, &env).unwrap().pr_str(true));
+
+        let mal = read_str(
+            "(def! fib (fn* (N) (if (= N 0) 1 (if (= N 1) 1 (+ (fib (- N 1)) (fib (- N 2)))))))",
+        )
+        .unwrap();
+        eval(mal, &env).unwrap();
+        let mal = read_str("(fib 4)").unwrap();
+        assert_eq!("5", eval(mal, &env).unwrap().pr_str(true));
+
+        let mal = read_str("(def! sum2 (fn* (n acc) (if (= n 0) acc (sum2 (- n 1) (+ n acc)))))")
+            .unwrap();
+        eval(mal, &env).unwrap();
+        let mal = read_str("(sum2 10 0)").unwrap();
+        assert_eq!("55", eval(mal, &env).unwrap().pr_str(true));
+
+        let mal = read_str("(def! res2 nil)").unwrap();
+        assert_eq!("nil", eval(mal, &env).unwrap().pr_str(true));
+
+        let mal = read_str("(def! res2 (sum2 10000 0))").unwrap();
+        assert_eq!("50005000", eval(mal, &env).unwrap().pr_str(true));
+        let mal = read_str("res2").unwrap();
+        assert_eq!("50005000", eval(mal, &env).unwrap().pr_str(true));
+    }
+
+    #[test]
+    fn step5_tail_calls() {
+        let env = Rc::new(Env::default());
+        // Functions receive themselves as arguments here, so recursion does not depend on
+        // `def!` being visible from inside the closure.
+        let mal = read_str(
+            "(def! sum2 (fn* (self n acc) (if (= n 0) acc (self self (- n 1) (+ n acc)))))",
+        )
+        .unwrap();
+        eval(mal, &env).unwrap();
+        let mal = read_str("(sum2 sum2 10000 0)").unwrap();
+        assert_eq!("50005000", eval(mal, &env).unwrap().pr_str(true));
+
+        let mal =
+            read_str("(def! foo (fn* (foo bar n) (if (= n 0) 0 (bar foo bar (- n 1)))))").unwrap();
+        eval(mal, &env).unwrap();
+        let mal =
+            read_str("(def! bar (fn* (foo bar n) (if (= n 0) 0 (foo foo bar (- n 1)))))").unwrap();
+        eval(mal, &env).unwrap();
+        let mal = read_str("(foo foo bar 10000)").unwrap();
+        assert_eq!("0", eval(mal, &env).unwrap().pr_str(true));
+
+        let mal = read_str("(let* (n 3) (do 1 2 (if false 4 (let* (m 5) (+ n m)))))").unwrap();
+        assert_eq!("8", eval(mal, &env).unwrap().pr_str(true));
+    }
+
+    #[test]
+    fn test_stdlib() {
+        let hash = HashMap::from([
+            (r#"(= "" "")"#, "true"),
+            (r#"(= "abc" "abc")"#, "true"),
+            (r#"(= "abc" "")"#, "false"),
+            (r#"(= "abc" "")"#, "false"),
+            (r#"(= "" "abc")"#, "false"),
+            (r#"(= "abc" "def")"#, "false"),
+            (r#"(= "abc" "ABC")"#, "false"),
+            (r#"(if "" 9 4)"#, "9"),
+            ("(= 1 1)", "true"),
+            ("(= 1 2)", "false"),
+            ("(< 81 94)", "true"),
+            ("(<= 1 2)", "true"),
+            ("(>= 1 1)", "true"),
+            ("(>= 1 2)", "false"),
+            ("(list 1 2 3)", "(1 2 3)"),
+            ("(list)", "()"),
+            ("(count (list 1 2 3))", "3"),
+            ("(count (list))", "0"),
+            ("(list? 1 2)", "false"),
+            ("(list? (list 1) 2)", "true"),
+            ("(list? (list))", "true"),
+            ("(empty? (list))", "true"),
+        ]);
+        let env = Rc::new(Env::default());
+
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap().pr_str(true));
+        }
+    }
+
+    #[test]
+    fn step5() {
+        let env = Rc::new(Env::default());
+        let mal = read_str("(def! sum2 (fn* (n acc) (if (= n 0) acc (sum2 (- n 1) (+ n acc)))))")
+            .unwrap();
+        eval(mal, &env).unwrap();
+        let mal = read_str("(sum2 10 0)").unwrap();
+        assert_eq!("55", eval(mal, &env).unwrap().pr_str(true));
+
+        let mal = read_str("(def! res2 nil)").unwrap();
+        assert_eq!("nil", eval(mal, &env).unwrap().pr_str(true));
+
+        let mal = read_str("(def! res2 (sum2 10000 0))").unwrap();
+        assert_eq!("50005000", eval(mal, &env).unwrap().pr_str(true));
+        let mal = read_str("res2").unwrap();
+        assert_eq!("50005000", eval(mal, &env).unwrap().pr_str(true));
+
+        let mal = read_str("(def! foo (fn* (n) (if (= n 0) 0 (bar (- n 1)))))").unwrap();
+        eval(mal, &env).unwrap().pr_str(true);
+        let mal = read_str("(def! bar (fn* (n) (if (= n 0) 0 (foo (- n 1)))))").unwrap();
+        eval(mal, &env).unwrap().pr_str(true);
+        let mal = read_str("(foo 10000)").unwrap();
+        assert_eq!("0", eval(mal, &env).unwrap().pr_str(true));
+    }
+
+    #[test]
+    fn errors() {
+        let env = Rc::new(Env::default());
+        let hash = HashMap::from([
+            (
+                "(undefined 1 2)",
+                "Unable to find `undefined' in current environment",
+            ),
+            ("(+ 1 nope)", "Unable to find `nope' in current environment"),
+            (
+                "(+ 1 true)",
+                "Wrong number or type of arguments provided to operator `+'",
+            ),
+            (
+                "(if)",
+                "Wrong number of arguments to `if': expected 2 or 3, found 0",
+            ),
+            (
+                "(def! 1 2)",
+                "Expected symbol as the name in `def!' but found 1",
+            ),
+            (
+                "(let* (1 2) 3)",
+                "Expected symbol in `let*' bindings but found 1",
+            ),
+            (
+                "(let* (a) 3)",
+                "`let*' expects pairs of bindings, found 1 forms",
+            ),
+            (
+                "(fn* (a))",
+                "Wrong number of arguments to `fn*': expected 2, found 1",
+            ),
+            ("(1 2 3)", "Expected function but found 1"),
+            ("((get {} :missing) 1)", "Expected function but found nil"),
+            ("(+ 1", "Expected ) but found EOF"),
+            ("1/0", "Parse error: Division by zero in 1/0"),
+        ]);
+
+        for (input, output) in hash {
+            let err = read_str(input).and_then(|mal| eval(mal, &env)).unwrap_err();
+            assert_eq!(output, err.to_string());
+        }
+
+        // The environment is still usable after an error.
+        let mal = read_str("(do (def! a 1) (undefined) (def! a 2))").unwrap();
+        assert!(eval(mal, &env).is_err());
+        let mal = read_str("a").unwrap();
+        assert_eq!("1", eval(mal, &env).unwrap().pr_str(true));
+    }
+
+    #[test]
+    fn strings() {
+        let env = Rc::new(Env::default());
+        let hash = HashMap::from([
+            (r#""abc""#, r#""abc""#),
+            (r#""""#, r#""""#),
+            (r#""a\nb""#, r#""a\nb""#),
+            (r#""a\"b\\c""#, r#""a\"b\\c""#),
+            (r#"(str "a\nb")"#, r#""a\nb""#),
+            (r#"(str "abc" 1 "def" nil)"#, r#""abc1defnil""#),
+            (r#"(str)"#, r#""""#),
+            (r#"(pr-str "abc" 1)"#, r#""\"abc\" 1""#),
+            (r#"(pr-str "a\nb")"#, r#""\"a\\nb\"""#),
+            (r#"(pr-str (list 1 "x"))"#, r#""(1 \"x\")""#),
+            (r#"(str (list 1 "x"))"#, r#""(1 x)""#),
+            (r#"(= "a\"b" (str "a" "\"" "b"))"#, "true"),
+            (r#"(prn "a\nb")"#, "nil"),
+            (r#"(println "a\nb")"#, "nil"),
+        ]);
+
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap().pr_str(true));
+        }
+
+        let mal = read_str(r#""a\nb""#).unwrap();
+        assert_eq!("a\nb", mal.pr_str(false));
+
+        for input in [r#""abc"#, r#"""#, r#""abc\""#, r#"(str "a)"#] {
+            assert!(
+                matches!(read_str(input), Err(MalErr::ParseErr(_))),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn vectors_and_hash_maps() {
+        let env = Rc::new(Env::default());
+        let hash = HashMap::from([
+            ("[]", "[]"),
+            ("[1 2 (+ 1 2)]", "[1 2 3]"),
+            ("{}", "{}"),
+            (r#"{"a" (+ 1 2)}"#, r#"{"a" 3}"#),
+            ("{:a {:b [1 (+ 1 1)]}}", "{:a {:b [1 2]}}"),
+            ("(vector 1 2 3)", "[1 2 3]"),
+            ("(vector? [1])", "true"),
+            ("(vector? (list 1))", "false"),
+            ("(list? [1])", "false"),
+            ("(count [1 2])", "2"),
+            ("(empty? [])", "true"),
+            (r#"(hash-map "a" 1)"#, r#"{"a" 1}"#),
+            ("(map? {})", "true"),
+            ("(map? [])", "false"),
+            ("(assoc {} :a 1)", "{:a 1}"),
+            ("(dissoc {:a 1} :a)", "{}"),
+            ("(get {:a 1} :a)", "1"),
+            (r#"(get {:a 1} "a")"#, "nil"),
+            ("(get nil :a)", "nil"),
+            ("(contains? {:a nil} :a)", "true"),
+            ("(contains? {:a nil} :b)", "false"),
+            ("(keys {:a 1})", "(:a)"),
+            ("(keys {\"ʞ:a\" 1})", "(\"ʞ:a\")"),
+            ("(= {\"ʞ:a\" 1} {:a 1})", "false"),
+            ("(get {\":a\" 1} :a)", "nil"),
+            ("(count (keys {\"a\" 1 :a 2}))", "2"),
+            ("(vals {:a 1})", "(1)"),
+            ("(let* [a 1 b (+ a 1)] [a b])", "[1 2]"),
+            ("((fn* [a b] (+ a b)) 1 2)", "3"),
+        ]);
+
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap().pr_str(true));
+        }
+
+        let mal = read_str(r#"(def! m (assoc {:a 1} "b" 2 :c 3))"#).unwrap();
+        eval(mal, &env).unwrap();
+        let mal = read_str(r#"(list (count (keys m)) (get m :a) (get m "b") (get m :c))"#).unwrap();
+        assert_eq!("(3 1 2 3)", eval(mal, &env).unwrap().pr_str(true));
+
+        assert!(read_str("{:a}").is_err());
+        assert!(read_str("{1 2}").is_err());
+        assert!(read_str("[1 2").is_err());
+    }
+
+    #[test]
+    fn step7_quote() {
+        let hash = HashMap::from([
+            ("'1", "(quote 1)"),
+            ("'(1 2 3)", "(quote (1 2 3))"),
+            ("`(1 2 3)", "(quasiquote (1 2 3))"),
+            ("~(1 2 3)", "(unquote (1 2 3))"),
+            ("`(1 ~a 3)", "(quasiquote (1 (unquote a) 3))"),
+            ("~@(1 2 3)", "(splice-unquote (1 2 3))"),
+            ("@a", "(deref a)"),
+            (r#"^{"a" 1} [1 2 3]"#, r#"(with-meta [1 2 3] {"a" 1})"#),
+        ]);
+
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, mal.pr_str(true));
+        }
+
+        let env = Rc::new(Env::default());
+        let mal = read_str("(def! lst '(b c))").unwrap();
+        eval(mal, &env).unwrap();
+        let hash = HashMap::from([
+            ("(quote abc)", "abc"),
+            ("'(1 b (+ 1 2))", "(1 b (+ 1 2))"),
+            ("(cons 1 (list 2))", "(1 2)"),
+            ("(cons [1] [2 3])", "([1] 2 3)"),
+            ("(concat (list 1 2) [3] ())", "(1 2 3)"),
+            ("(concat)", "()"),
+            ("(vec (list 1 2))", "[1 2]"),
+            ("`7", "7"),
+            ("`a", "a"),
+            ("`(1 a 3)", "(1 a 3)"),
+            ("`(1 ~lst 3)", "(1 (b c) 3)"),
+            ("`(1 ~@lst 3)", "(1 b c 3)"),
+            ("`[1 ~@lst]", "[1 b c]"),
+            ("`{:a b}", "{:a b}"),
+            ("`(~(+ 1 2))", "(3)"),
+            ("(quasiquoteexpand (1 ~a))", "(cons 1 (cons a ()))"),
+            ("(quasiquoteexpand [~@a])", "(vec (concat a ()))"),
+        ]);
+
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap().pr_str(true));
+        }
+    }
+
+    #[test]
+    fn step8_macros() {
+        let env = root_env();
+        // Order matters here, the macros are defined by earlier forms.
+        let forms = [
+            ("(defmacro! one (fn* () 1))", "<user:macro>"),
+            ("(one)", "1"),
+            (
+                "(defmacro! unless (fn* (p a b) `(if ~p ~b ~a)))",
+                "<user:macro>",
+            ),
+            ("(unless false 7 8)", "7"),
+            ("(unless true 7 8)", "8"),
+            ("(macroexpand (unless 2 3 4))", "(if 2 4 3)"),
+            ("(macroexpand (one))", "1"),
+            ("(macroexpand (+ 1 2))", "(+ 1 2)"),
+            ("(defmacro! identity (fn* (x) x))", "<user:macro>"),
+            ("(let* (a 123) (identity a))", "123"),
+            ("((fn* (a & more) (list a more)) 1 2 3)", "(1 (2 3))"),
+            ("((fn* (& more) more))", "()"),
+            ("(first (list 1 2))", "1"),
+            ("(first [])", "nil"),
+            ("(first nil)", "nil"),
+            ("(rest [1 2 3])", "(2 3)"),
+            ("(rest nil)", "()"),
+            ("(nth [1 2 3] 2)", "3"),
+            ("(cond)", "nil"),
+            ("(cond true 7)", "7"),
+            ("(cond false 7 true 8)", "8"),
+            ("(cond false 7 false 8 \"else\" 9)", "9"),
+            ("(cond false 7 false 8)", "nil"),
+            ("(or)", "nil"),
+            ("(or 1)", "1"),
+            ("(or false nil 3)", "3"),
+            ("(or false nil)", "nil"),
+            ("(let* (x (or nil \"yes\")) x)", "\"yes\""),
+            ("(let* (or_FIXME 5) (or false or_FIXME))", "5"),
+            ("(= (gensym) (gensym))", "false"),
+        ];
+
+        for (input, output) in forms {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap().pr_str(true));
+        }
+
+        let mal = read_str("(nth (list 1) 1)").unwrap();
+        assert!(eval(mal, &env).is_err());
+        // The throw comes from the prelude, so it points there.
+        let mal = read_str("(cond true)").unwrap();
+        let err = eval(mal, &env).unwrap_err();
+        assert!(err.to_string().starts_with("prelude.mal:"), "{err}");
+        assert!(matches!(err.into_inner(), MalErr::Throw(_)));
+    }
+
+    #[test]
+    fn step6_atoms() {
+        let env = root_env();
+        let forms = [
+            ("(def! inc3 (fn* (a) (+ 3 a)))", "<user:fn>"),
+            ("(def! a (atom 2))", "(atom 2)"),
+            ("(atom? a)", "true"),
+            ("(atom? 1)", "false"),
+            ("(deref a)", "2"),
+            ("@a", "2"),
+            ("(reset! a 3)", "3"),
+            ("@a", "3"),
+            ("(swap! a inc3)", "6"),
+            ("(swap! a (fn* (a) a))", "6"),
+            ("(swap! a (fn* (a) (* 2 a)))", "12"),
+            ("(swap! a (fn* (a b) (* a b)) 10)", "120"),
+            ("(swap! a + 3)", "123"),
+            ("@a", "123"),
+            ("(def! inc-it (fn* () (swap! a + 1)))", "<user:fn>"),
+            ("(do (inc-it) (inc-it) @a)", "125"),
+            (r#"(atom "s")"#, r#"(atom "s")"#),
+            (
+                "(let* (b (atom nil)) (do (reset! b b) b))",
+                "(atom (atom ...))",
+            ),
+            (
+                "(let* (b (atom nil)) (do (reset! b [1 b]) (str b)))",
+                r#""(atom [1 (atom ...)])""#,
+            ),
+            ("(let* (b (atom 1)) (list b b))", "((atom 1) (atom 1))"),
+        ];
+
+        for (input, output) in forms {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap().pr_str(true));
+        }
+
+        let mal = read_str("(let* (b (atom nil)) (do (reset! b b) (throw b)))").unwrap();
+        let err = eval(mal, &env).unwrap_err();
+        assert_eq!("Uncaught exception: (atom (atom ...))", err.to_string());
+    }
+
+    #[test]
+    fn native_closures() {
+        let env = root_env();
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        env.set(
+            "count-calls",
+            MalType::func("count-calls", move |_| {
+                counter.set(counter.get() + 1);
+                Ok(MalType::Int(counter.get()))
+            }),
+        );
+
+        let mal = read_str("(do (count-calls) (count-calls) (count-calls))").unwrap();
+        assert_eq!("3", eval(mal, &env).unwrap().pr_str(true));
+        assert_eq!(3, calls.get());
+
+        let mal = read_str("count-calls").unwrap();
+        assert_eq!(
+            "<std:fn count-calls>",
+            eval(mal, &env).unwrap().pr_str(true)
+        );
+        let mal = read_str("+").unwrap();
+        assert_eq!("<std:fn +>", eval(mal, &env).unwrap().pr_str(true));
+    }
+
+    #[test]
+    fn higher_order_builtins() {
+        let env = root_env();
+        let mal = read_str("(def! inc (fn* (a) (+ a 1)))").unwrap();
+        eval(mal, &env).unwrap();
+        let hash = HashMap::from([
+            ("(apply + (list 2 3))", "5"),
+            ("(apply + 4 [5])", "9"),
+            ("(apply inc [1])", "2"),
+            ("(apply list 1 2 (list 3 4))", "(1 2 3 4)"),
+            ("(map inc [1 2 3])", "(2 3 4)"),
+            ("(map list (list 1 2))", "((1) (2))"),
+            ("(map inc ())", "()"),
+            ("(filter (fn* (a) (> a 1)) [1 2 3])", "(2 3)"),
+            ("(filter list? (list 1 (list 2) [3]))", "((2))"),
+            ("(reduce + 0 [1 2 3])", "6"),
+            ("(reduce + [1 2 3])", "6"),
+            ("(reduce (fn* (acc a) (cons a acc)) () [1 2 3])", "(3 2 1)"),
+            ("(sort-by (fn* (a) (- 0 a)) [1 3 2])", "(3 2 1)"),
+            (
+                r#"(sort-by first [["b" 1] ["a" 2]])"#,
+                r#"(["a" 2] ["b" 1])"#,
+            ),
+            (
+                "(sort-by (fn* (m) (get m :k)) [{:k 2} {:k 1}])",
+                "({:k 1} {:k 2})",
+            ),
+        ]);
+
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap().pr_str(true));
+        }
+
+        let mal = read_str("(map (fn* (a) (throw a)) [1 2])").unwrap();
+        assert!(matches!(eval(mal, &env), Err(MalErr::Throw(_))));
+        let mal = read_str("(sort-by (fn* (a) a) [1 :a])").unwrap();
+        assert!(eval(mal, &env).is_err());
+        // Keys of mixed kinds are an error rather than an inconsistent order for the sort.
+        let mixed = (0..60)
+            .map(|i| match i % 3 {
+                0 => format!(":k{}", (i * 37) % 11),
+                _ => ((i * 7919) % 101).to_string(),
+            })
+            .join(" ");
+        let mal = read_str(&format!("(sort-by (fn* (x) x) [{mixed}])")).unwrap();
+        assert_eq!(
+            "Cannot compare :k0 with 41",
+            eval(mal, &env).unwrap_err().to_string()
+        );
+        let mal = read_str("(sort-by (fn* (x) x) [1 2 ##NaN])").unwrap();
+        assert!(eval(mal, &env).is_err());
+    }
+
+    #[test]
+    fn step6_files() {
+        let env = root_env();
+        let path = std::env::temp_dir().join(format!("mal-step6-{}.mal", std::process::id()));
+        std::fs::write(
+            &path,
+            "(def! inc4 (fn* (a) (+ 4 a)))\n; comment\n(def! loaded \"yes\")\n",
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+
+        let forms = [
+            ("(read-string \"(1 2 (3 4) nil)\")", "(1 2 (3 4) nil)"),
+            ("(read-string \"7 ;; comment\")", "7"),
+            ("(read-string \"\\\"a\\\"\")", "\"a\""),
+            ("(eval (read-string \"(+ 2 3)\"))", "5"),
+            ("(eval (list + 1 2))", "3"),
+            (
+                "(let* (b 12) (do (eval (read-string \"(def! aa 7)\")) aa))",
+                "7",
+            ),
+            ("(let* (aa 1) (eval 'aa))", "7"),
+            (&format!("(load-file \"{path}\")"), "nil"),
+            ("(inc4 3)", "7"),
+            ("loaded", "\"yes\""),
+        ];
+
+        for (input, output) in forms {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap().pr_str(true));
+        }
+
+        let mal = read_str(&format!("(slurp \"{path}\")")).unwrap();
+        let src = std::fs::read_to_string(path).unwrap();
+        assert_eq!(src, eval(mal, &env).unwrap().pr_str(false));
+        std::fs::remove_file(path).unwrap();
+
+        let mal = read_str("(slurp \"/nonexistent/file.mal\")").unwrap();
+        assert!(eval(mal, &env).is_err());
+    }
+
+    #[test]
+    fn step9_try_catch() {
+        let env = Rc::new(Env::default());
+        let hash = HashMap::from([
+            ("(try* 123 (catch* e 456))", "123"),
+            ("(try* (throw 7) (catch* e (+ e 1)))", "8"),
+            ("(try* (throw (list 1 2)) (catch* exc exc))", "(1 2)"),
+            (
+                "(try* (abc 1 2) (catch* exc exc))",
+                r#""Unable to find `abc' in current environment""#,
+            ),
+            (
+                "(try* (+ 1 nil) (catch* exc exc))",
+                r#""Wrong number or type of arguments provided to operator `+'""#,
+            ),
+            (
+                "(try* (do (try* (throw 1) (catch* e (throw (+ e 1))))) (catch* e e))",
+                "2",
+            ),
+            ("(try* (+ 2 3))", "5"),
+            ("(let* (e 1) (do (try* (throw 2) (catch* e e)) e))", "1"),
+        ]);
+
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap().pr_str(true));
+        }
+
+        let mal = read_str("(throw 42)").unwrap();
+        let err = eval(mal, &env).unwrap_err();
+        assert_eq!("Uncaught exception: 42", err.to_string());
+
+        let hash = HashMap::from([
+            (
+                "(try* 1 2)",
+                "Expected (catch* symbol body) in `try*' but found 2",
+            ),
+            (
+                "(try* 1 (catch* 2 3))",
+                "Expected (catch* symbol body) in `try*' but found (catch* 2 3)",
+            ),
+            (
+                "(try* 1 2 3)",
+                "Wrong number of arguments to `try*': expected 1 or 2, found 3",
+            ),
+        ]);
+        for (input, output) in hash {
+            let mal = read_str(input).unwrap();
+            assert_eq!(output, eval(mal, &env).unwrap_err().to_string(), "{input}");
+        }
+    }
+}
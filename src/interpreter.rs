@@ -0,0 +1,201 @@
+use crate::convert::IntoNativeFn;
+use crate::env::Env;
+use crate::eval::{apply_as, eval};
+use crate::reader::{read_all, read_source, read_str};
+use crate::types::{MalErr, MalType};
+use std::rc::Rc;
+
+const PRELUDE: &str = include_str!("prelude.mal");
+
+/// The root environment with the core namespace and everything defined in `prelude.mal`.
+pub(crate) fn root_env() -> Rc<Env> {
+    let env = Rc::new(Env::default());
+    for form in read_source(PRELUDE, "prelude.mal").expect("prelude.mal should parse") {
+        eval(form, &env).expect("prelude.mal should evaluate");
+    }
+    env
+}
+
+/// An embeddable Mal interpreter. Everything it evaluates shares one root environment, so a
+/// `def!` made by one call is visible to the next.
+pub struct Interpreter {
+    env: Rc<Env>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter { env: root_env() }
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads a single form from `src` and evaluates it.
+    pub fn eval_str(&self, src: &str) -> Result<MalType, MalErr> {
+        self.eval_form(read_str(src)?)
+    }
+
+    /// Evaluates every form in `src` in order, returning the value of the last one.
+    pub fn eval_all(&self, src: &str) -> Result<MalType, MalErr> {
+        let mut ret = MalType::Nil;
+        for form in read_all(src)? {
+            ret = self.eval_form(form)?;
+        }
+        Ok(ret)
+    }
+
+    /// Like [`Interpreter::eval_all`], but errors point at their line and column in `src`,
+    /// which is called `name` in the messages.
+    pub fn eval_source(&self, src: &str, name: &str) -> Result<MalType, MalErr> {
+        let mut ret = MalType::Nil;
+        for form in read_source(src, name)? {
+            ret = self.eval_form(form)?;
+        }
+        Ok(ret)
+    }
+
+    pub fn eval_form(&self, form: MalType) -> Result<MalType, MalErr> {
+        eval(form, &self.env)
+    }
+
+    /// Binds `name` to `val` in the root environment.
+    pub fn define(&self, name: &str, val: MalType) {
+        self.env.set(name, val);
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<MalType> {
+        self.env.get(name)
+    }
+
+    /// Binds `name` to a Rust function taking and returning values that convert to and from
+    /// Mal, e.g. `|a: i64, b: i64| a + b`. The arity and argument types are checked on each call.
+    pub fn register_fn<Args, F: IntoNativeFn<Args>>(&self, name: &str, f: F) {
+        self.define(name, f.into_native(name));
+    }
+
+    /// Calls the function bound to `name` with already evaluated `args`.
+    pub fn call(&self, name: &str, args: Vec<MalType>) -> Result<MalType, MalErr> {
+        match self.lookup(name) {
+            Some(f) => apply_as(
+                Some(&MalType::Sym(name.to_string(), None)),
+                &f,
+                args,
+                &self.env,
+            ),
+            None => Err(MalErr::SymbolNotFound(name.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Interpreter, MalErr, MalType};
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    #[test]
+    fn embedding() {
+        let interp = Interpreter::new();
+        interp.define("answer", MalType::Int(42));
+        interp.define(
+            "double",
+            MalType::func("double", |args| match args {
+                [MalType::Int(a)] => Ok(MalType::Int(a * 2)),
+                _ => Err(MalErr::E("`double' expects an integer".to_string())),
+            }),
+        );
+
+        assert_eq!(
+            "84",
+            interp.eval_str("(double answer)").unwrap().pr_str(true)
+        );
+        interp.eval_str("(def! add (fn* (a b) (+ a b)))").unwrap();
+        assert_eq!(
+            "5",
+            interp
+                .call("add", vec![MalType::Int(2), MalType::Int(3)])
+                .unwrap()
+                .pr_str(true)
+        );
+        assert_eq!(
+            "6",
+            interp
+                .call("+", vec![MalType::Int(2), MalType::Int(4)])
+                .unwrap()
+                .pr_str(true)
+        );
+        assert_eq!(
+            "Wrong number of arguments to `add': expected 2, found 1",
+            interp
+                .call("add", vec![MalType::Int(2)])
+                .unwrap_err()
+                .to_string()
+        );
+        assert!(interp.lookup("add").is_some());
+        assert!(interp.lookup("missing").is_none());
+        assert!(matches!(
+            interp.call("missing", vec![]),
+            Err(MalErr::SymbolNotFound(_))
+        ));
+
+        let form = MalType::List(
+            Rc::new(vec![
+                MalType::Sym("double".to_string(), None),
+                MalType::Int(4),
+            ]),
+            None,
+        );
+        assert_eq!("8", interp.eval_form(form).unwrap().pr_str(true));
+        assert_eq!(
+            "1",
+            interp
+                .eval_str("(cond false 0 true 1)")
+                .unwrap()
+                .pr_str(true)
+        );
+    }
+
+    struct Row {
+        id: i64,
+    }
+
+    #[test]
+    fn opaque_values() {
+        let interp = Interpreter::new();
+        interp.define("row", MalType::opaque(Row { id: 7 }));
+        interp.register_fn("row-id", |row: Rc<Row>| row.id);
+        interp.register_fn("new-row", |id: i64| Rc::new(Row { id }));
+
+        let row = interp.lookup("row").unwrap();
+        assert_eq!(7, row.downcast_ref::<Row>().unwrap().id);
+        assert!(row.downcast_ref::<String>().is_none());
+        assert!(MalType::Int(1).downcast_ref::<Row>().is_none());
+
+        let hash = HashMap::from([
+            ("row", "<opaque:mal::interpreter::tests::Row>"),
+            ("(row-id row)", "7"),
+            ("(row-id (new-row 3))", "3"),
+            ("(= row row)", "true"),
+            ("(let* (r row) (= r row))", "true"),
+            ("(= row (new-row 7))", "false"),
+            (
+                "(first (list row))",
+                "<opaque:mal::interpreter::tests::Row>",
+            ),
+        ]);
+        for (input, output) in hash {
+            assert_eq!(output, interp.eval_str(input).unwrap().pr_str(true));
+        }
+
+        let err = interp.eval_str("(row-id 1)").unwrap_err();
+        assert_eq!(
+            "Expected <opaque:mal::interpreter::tests::Row> as argument 1 to `row-id' but found 1",
+            err.to_string()
+        );
+        let err = interp.eval_str(r#"(row-id (atom 1))"#).unwrap_err();
+        assert!(matches!(err, MalErr::WrongType { .. }));
+    }
+}
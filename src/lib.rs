@@ -0,0 +1,12 @@
+pub mod convert;
+pub mod core;
+pub mod env;
+mod eval;
+mod interpreter;
+pub mod reader;
+pub mod span;
+pub mod types;
+
+pub use crate::convert::{FromMal, IntoMal};
+pub use crate::interpreter::Interpreter;
+pub use crate::types::{Context, MalErr, MalType, MapKey};
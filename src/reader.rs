@@ -1,49 +1,184 @@
+use crate::span::{Source, Span};
 use crate::types::MalErr;
 use crate::types::MalType;
-use regex::Regex;
-use std::process::exit;
+use itertools::Itertools;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::Zero;
+use std::collections::HashMap;
 use std::rc::Rc;
 
-struct Reader {
-    tokens: Vec<String>,
-    pos: usize,
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    col: usize,
 }
 
-impl Reader {
-    fn next(&mut self) -> Option<String> {
-        self.pos += 1;
-        self.tokens.get(self.pos - 1).map(|token| token.to_owned())
+/// Splits source text into tokens in a single pass. Tokens borrow from the text, and
+/// whitespace, commas and comments between them are skipped.
+struct Lexer<'a> {
+    src: &'a str,
+    /// Byte offset of the next character.
+    offset: usize,
+    line: usize,
+    col: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(src: &'a str) -> Self {
+        Lexer {
+            src,
+            offset: 0,
+            line: 1,
+            col: 1,
+        }
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.src[self.offset..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek_char()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    fn bump_while(&mut self, pred: impl Fn(char) -> bool) {
+        while self.peek_char().is_some_and(&pred) {
+            self.bump();
+        }
+    }
+
+    /// The rest of a string after its opening quote. A string that runs into the end of the
+    /// input, or into a backslash at the end of a line, stops there without a closing quote.
+    fn string(&mut self) {
+        while let Some(c) = self.peek_char() {
+            match c {
+                '"' => {
+                    self.bump();
+                    return;
+                }
+                '\\' => match self.src[self.offset + 1..].chars().next() {
+                    Some(next) if next != '\n' => {
+                        self.bump();
+                        self.bump();
+                    }
+                    _ => return,
+                },
+                _ => {
+                    self.bump();
+                }
+            }
+        }
     }
+}
+
+fn is_symbol_char(c: char) -> bool {
+    !c.is_whitespace() && !"[]{}('\"`,;)".contains(c)
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
 
-    fn peek(&self) -> Option<String> {
-        self.tokens.get(self.pos).map(|token| token.to_owned())
+    fn next(&mut self) -> Option<Token<'a>> {
+        loop {
+            self.bump_while(|c| c.is_whitespace() || c == ',');
+            let (start, line, col) = (self.offset, self.line, self.col);
+            match self.bump()? {
+                ';' => {
+                    self.bump_while(|c| c != '\n');
+                    continue;
+                }
+                '~' if self.peek_char() == Some('@') => {
+                    self.bump();
+                }
+                '[' | ']' | '{' | '}' | '(' | ')' | '\'' | '`' | '~' | '^' | '@' => {}
+                '"' => self.string(),
+                _ => self.bump_while(is_symbol_char),
+            }
+            let text = &self.src[start..self.offset];
+            return Some(Token { text, line, col });
+        }
     }
 }
 
-fn tokenize(s: &str) -> Vec<String> {
-    let reg =
-        Regex::new(r###"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]*)"###)
-            .expect("Invalid regular expression provided");
+struct Reader<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    /// Line and column just past the last token, where EOF is reported.
+    end: (usize, usize),
+    /// Set when reading with positions, see [`read_source`].
+    source: Option<Rc<Source>>,
+}
 
-    let mut vec = vec![];
-    for cap in reg.captures_iter(s) {
-        // comment
-        if cap[1].starts_with(';') {
-            continue;
+impl<'a> Reader<'a> {
+    fn new(s: &'a str, source: Option<Rc<Source>>) -> Self {
+        let mut lexer = Lexer::new(s);
+        let tokens = lexer.by_ref().collect();
+        Reader {
+            tokens,
+            pos: 0,
+            end: (lexer.line, lexer.col),
+            source,
         }
-        vec.push(String::from(&cap[1]))
     }
-    vec
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.pos += 1;
+        self.tokens.get(self.pos - 1).map(|token| token.text)
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|token| token.text)
+    }
+
+    /// Where token `i` starts, or the end of the input past the last token.
+    fn span(&self, i: usize) -> Option<Span> {
+        let source = self.source.clone()?;
+        let (line, col) = match self.tokens.get(i) {
+            Some(token) => (token.line, token.col),
+            None => self.end,
+        };
+        Some(Span { source, line, col })
+    }
+
+    fn error_at(&self, i: usize, err: MalErr) -> MalErr {
+        err.at(self.span(i))
+    }
+
+    /// Where token `i` starts, to keep on the form read from it.
+    fn form_span(&self, i: usize) -> Option<Rc<Span>> {
+        self.span(i).map(Rc::new)
+    }
+
+    /// The symbol `name` read at token `i`, remembering where it is.
+    fn sym(&self, i: usize, name: &str) -> MalType {
+        MalType::Sym(name.to_string(), self.form_span(i))
+    }
 }
 
-fn read_list(rd: &mut Reader, end: &str) -> Result<MalType, MalErr> {
+/// Reads the forms up to `end`. `start` is the index of the opening token, where a missing
+/// `end` is reported.
+fn read_list(rd: &mut Reader, start: usize, end: &str) -> Result<Vec<MalType>, MalErr> {
     let mut vec: Vec<MalType> = vec![];
     loop {
         let token = match rd.peek() {
             Some(t) => t,
             None => {
-                eprintln!("Expected {end} but found EOF");
-                return Err(MalErr::UnexpectedToken);
+                return Err(rd.error_at(
+                    start,
+                    MalErr::UnexpectedToken {
+                        expected: end.to_string(),
+                        found: "EOF".to_string(),
+                    },
+                ))
             }
         };
         if token == end {
@@ -52,53 +187,279 @@ fn read_list(rd: &mut Reader, end: &str) -> Result<MalType, MalErr> {
         vec.push(read_form(rd)?);
     }
     let _ = rd.next(); // skip ")"
-    Ok(MalType::List(Rc::new(vec)))
-}
-
-fn read_atom(rd: &mut Reader) -> MalType {
-    let num_re = Regex::new(r"^-?[0-9]+$").expect("Invalid regular expression for number");
-    let str_re = Regex::new(r#"^"(.)*"$"#).expect("Invalid regular expression for string");
-    let key_re = Regex::new(r"^:(.*)*$").expect("Invalid regular expression for keyword");
-    match rd.next() {
-        Some(token) => match &token[..] {
-            "nil" => MalType::Nil,
-            "true" => MalType::Bool(true),
-            "false" => MalType::Bool(false),
-            _ => {
-                if num_re.is_match(&token) {
-                    MalType::Int(token.parse().unwrap())
-                } else if str_re.is_match(&token) {
-                    MalType::Str(token.to_string())
-                } else if key_re.is_match(&token) {
-                    MalType::Keyword(token.to_string())
-                } else {
-                    MalType::Sym(token.to_string())
+    Ok(vec)
+}
+
+fn read_hash_map(rd: &mut Reader, start: usize) -> Result<MalType, MalErr> {
+    let vec = read_list(rd, start, "}")?;
+    if !vec.len().is_multiple_of(2) {
+        return Err(rd.error_at(
+            start,
+            MalErr::ParseErr(format!(
+                "Hash-map literal needs an even number of forms, found {}",
+                vec.len()
+            )),
+        ));
+    }
+    let mut map = HashMap::new();
+    for (key, val) in vec.into_iter().tuples() {
+        map.insert(key.to_key().map_err(|e| rd.error_at(start, e))?, val);
+    }
+    Ok(MalType::HashMap(Rc::new(map), rd.form_span(start)))
+}
+
+/// Strips the quotes off a string token and replaces the `\"`, `\\` and `\n` escapes. Any
+/// other escaped character is kept as is, backslash included.
+fn unescape(token: &str) -> Result<String, MalErr> {
+    let mut ret = String::new();
+    let mut chars = token.chars().skip(1);
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Ok(ret),
+            '\\' => match chars.next() {
+                Some('"') => ret.push('"'),
+                Some('\\') => ret.push('\\'),
+                Some('n') => ret.push('\n'),
+                Some(c) => {
+                    ret.push('\\');
+                    ret.push(c);
                 }
-            } // any_thing => MalType::Int(any_thing.parse::<i32>().expect("unable to parse int")),
-        },
-        None => {
-            eprintln!("Expected token found none");
-            exit(1);
+                None => break,
+            },
+            c => ret.push(c),
+        }
+    }
+    Err(MalErr::ParseErr(format!("Unterminated string {token}")))
+}
+
+/// Whether `token` is an integer literal: digits with an optional leading minus.
+fn is_int(token: &str) -> bool {
+    let digits = token.strip_prefix('-').unwrap_or(token);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// The numerator and denominator of a ratio literal such as `22/7` or `-1/3`.
+fn ratio(token: &str) -> Option<(BigInt, BigInt)> {
+    let (numer, denom) = token.split_once('/')?;
+    if !is_int(numer) || !is_int(denom) || denom.starts_with('-') {
+        return None;
+    }
+    Some((numer.parse().ok()?, denom.parse().ok()?))
+}
+
+/// Whether `token` is a float literal: an integer followed by a fraction, an exponent or both,
+/// e.g. `1.5`, `-0.25`, `1e3` or `2.5E-3`.
+fn is_float(token: &str) -> bool {
+    let digits = |s: &str| s.bytes().take_while(u8::is_ascii_digit).count();
+    let rest = token.strip_prefix('-').unwrap_or(token);
+    let int = digits(rest);
+    if int == 0 {
+        return false;
+    }
+    let mut rest = &rest[int..];
+    let fraction = rest.starts_with('.');
+    if fraction {
+        rest = &rest[1 + digits(&rest[1..])..];
+    }
+    let exponent = rest.starts_with(['e', 'E']);
+    if exponent {
+        rest = rest[1..].strip_prefix(['+', '-']).unwrap_or(&rest[1..]);
+        let exp = digits(rest);
+        if exp == 0 {
+            return false;
         }
+        rest = &rest[exp..];
     }
+    (fraction || exponent) && rest.is_empty()
+}
+
+fn read_atom(rd: &mut Reader) -> Result<MalType, MalErr> {
+    let start = rd.pos;
+    let token = match rd.next() {
+        Some(token) => token,
+        None => {
+            return Err(MalErr::UnexpectedToken {
+                expected: "atom".to_string(),
+                found: "EOF".to_string(),
+            })
+        }
+    };
+    Ok(match token {
+        "nil" => MalType::Nil,
+        "true" => MalType::Bool(true),
+        "false" => MalType::Bool(false),
+        "##Inf" => MalType::Float(f64::INFINITY),
+        "##-Inf" => MalType::Float(f64::NEG_INFINITY),
+        "##NaN" => MalType::Float(f64::NAN),
+        _ => {
+            if is_int(token) {
+                match token.parse() {
+                    Ok(num) => MalType::Int(num),
+                    Err(_) => MalType::Bignum(Rc::new(
+                        token.parse().expect("Integer literals are valid bignums"),
+                    )),
+                }
+            } else if let Some((numer, denom)) = ratio(token) {
+                if denom.is_zero() {
+                    let err = MalErr::ParseErr(format!("Division by zero in {token}"));
+                    return Err(rd.error_at(start, err));
+                }
+                MalType::ratio(BigRational::new(numer, denom))
+            } else if is_float(token) {
+                MalType::Float(token.parse().expect("Float literals are valid f64"))
+            } else if token.starts_with('"') {
+                MalType::Str(unescape(token).map_err(|e| rd.error_at(start, e))?)
+            } else if token.starts_with(':') {
+                MalType::Keyword(token.to_string())
+            } else {
+                rd.sym(start, token)
+            }
+        }
+    })
+}
+
+/// Expands a reader macro such as `'x` into `(quote x)`.
+fn read_macro(rd: &mut Reader, sym: &str) -> Result<MalType, MalErr> {
+    let start = rd.pos;
+    let _ = rd.next();
+    let form = read_form(rd)?;
+    Ok(MalType::List(
+        Rc::new(vec![rd.sym(start, sym), form]),
+        rd.form_span(start),
+    ))
 }
 
 fn read_form(rd: &mut Reader) -> Result<MalType, MalErr> {
+    let start = rd.pos;
     match rd.peek() {
-        Some(token) => match &token[..] {
+        Some(token) => match token {
             "(" => {
                 let _ = rd.next();
-                read_list(rd, ")")
+                let vec = read_list(rd, start, ")")?;
+                Ok(MalType::List(Rc::new(vec), rd.form_span(start)))
             }
-            _ => Ok(read_atom(rd)),
+            "[" => {
+                let _ = rd.next();
+                let vec = read_list(rd, start, "]")?;
+                Ok(MalType::Vector(Rc::new(vec), rd.form_span(start)))
+            }
+            "{" => {
+                let _ = rd.next();
+                read_hash_map(rd, start)
+            }
+            "'" => read_macro(rd, "quote"),
+            "`" => read_macro(rd, "quasiquote"),
+            "~" => read_macro(rd, "unquote"),
+            "~@" => read_macro(rd, "splice-unquote"),
+            "@" => read_macro(rd, "deref"),
+            "^" => {
+                let _ = rd.next();
+                let meta = read_form(rd)?;
+                let form = read_form(rd)?;
+                Ok(MalType::List(
+                    Rc::new(vec![rd.sym(start, "with-meta"), form, meta]),
+                    rd.form_span(start),
+                ))
+            }
+            ")" | "]" | "}" => {
+                Err(rd.error_at(start, MalErr::ParseErr(format!("Unexpected {token}"))))
+            }
+            _ => read_atom(rd),
         },
         None => Err(MalErr::ParseErr("No tokens found".to_string())),
     }
 }
 
+/// Reads a single form. Anything after it is an error rather than silently dropped.
 pub fn read_str(s: &str) -> Result<MalType, MalErr> {
-    let tokens = tokenize(s);
-    // println!("{:?}", tokens);
-    let mut reader = Reader { tokens, pos: 0 };
-    read_form(&mut reader)
+    let mut reader = Reader::new(s, None);
+    let form = read_form(&mut reader)?;
+    match reader.peek() {
+        Some(token) => Err(MalErr::ParseErr(format!(
+            "Unexpected {token} after the end of the form"
+        ))),
+        None => Ok(form),
+    }
+}
+
+/// Reads every top-level form in `s`, e.g. all the forms of a source file.
+pub fn read_all(s: &str) -> Result<Vec<MalType>, MalErr> {
+    read_forms(Reader::new(s, None))
+}
+
+/// Like [`read_all`], but errors point at their line and column in the source called `name`,
+/// and so do errors later raised while evaluating the forms.
+pub fn read_source(s: &str, name: &str) -> Result<Vec<MalType>, MalErr> {
+    let source = Rc::new(Source {
+        name: name.to_string(),
+        text: s.to_string(),
+    });
+    read_forms(Reader::new(s, Some(source)))
+}
+
+fn read_forms(mut reader: Reader) -> Result<Vec<MalType>, MalErr> {
+    let mut forms = vec![];
+    while reader.peek().is_some() {
+        forms.push(read_form(&mut reader)?);
+    }
+    Ok(forms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lexer;
+    use regex::Regex;
+
+    /// The regular expression the lexer replaced, which defines the tokenization rules.
+    fn regex_tokens(s: &str) -> Vec<(&str, usize)> {
+        let reg = Regex::new(
+            r###"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]*)"###,
+        )
+        .unwrap();
+        reg.captures_iter(s)
+            .map(|cap| cap.get(1).unwrap())
+            .filter(|m| !m.as_str().starts_with(';') && !m.as_str().is_empty())
+            .map(|m| (m.as_str(), m.start()))
+            .collect()
+    }
+
+    #[test]
+    fn lexer_matches_regex() {
+        let inputs = [
+            "(+ 1 2)",
+            "  ,,(def! a\t[1 2 3])\n; comment (\n{:a \"b\"}",
+            "'a `(b ~c ~@d) @e ^{:m 1} f ~ ~~@g",
+            r#""esc \" \\ \n" "unterminated"#,
+            "\"backslash at end of line\\\n(x)",
+            "\"unterminated at end\\",
+            "a~b^c@d -12 -x :kw:x nil true",
+            "\"multi\nline\" ü→(ö)\u{a0}x",
+            "abc;comment\n)",
+            "",
+            "   \n ; only a comment",
+        ];
+        for input in inputs {
+            let tokens: Vec<_> = Lexer::new(input).map(|t| t.text).collect();
+            let expected: Vec<_> = regex_tokens(input).into_iter().map(|(t, _)| t).collect();
+            assert_eq!(expected, tokens, "{input:?}");
+        }
+    }
+
+    #[test]
+    fn lexer_positions() {
+        let tokens: Vec<_> = Lexer::new("(a\n  \"x\ny\" ü\tb)")
+            .map(|t| (t.text, t.line, t.col))
+            .collect();
+        assert_eq!(
+            vec![
+                ("(", 1, 1),
+                ("a", 1, 2),
+                ("\"x\ny\"", 2, 3),
+                ("ü", 3, 4),
+                ("b", 3, 6),
+                (")", 3, 7),
+            ],
+            tokens
+        );
+    }
 }
@@ -0,0 +1,36 @@
+use std::fmt;
+use std::rc::Rc;
+
+/// Source text read with positions, e.g. a file loaded with `load-file`.
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+
+/// A position in a [`Source`]. Lines and columns start at 1 and columns count characters.
+#[derive(Clone)]
+pub struct Span {
+    pub source: Rc<Source>,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.source.name, self.line, self.col)
+    }
+}
+
+impl Span {
+    /// The source line the span is on followed by a caret under its column.
+    pub fn snippet(&self) -> String {
+        let line = self.source.text.lines().nth(self.line - 1).unwrap_or("");
+        // Keep tabs so that the caret lines up with the line above it.
+        let pad: String = line
+            .chars()
+            .take(self.col - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        format!("{line}\n{pad}^")
+    }
+}
@@ -1,20 +1,104 @@
 use crate::env::Env;
+use crate::span::Span;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
+use std::any::{self, Any};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
 use std::rc::Rc;
 
+/// A function implemented in Rust. Being a closure it can capture state of its own, for example
+/// a handle to host data.
+pub type NativeFn = Rc<dyn Fn(&Context, &[MalType]) -> Result<MalType, MalErr>>;
+
+/// The part of the interpreter a native function is called with, so that it can call back
+/// into Mal code such as a `MalFunc` passed to it as an argument.
+pub struct Context<'a> {
+    env: &'a Rc<Env>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(env: &'a Rc<Env>) -> Self {
+        Context { env }
+    }
+
+    /// The environment the native function was called from.
+    pub fn env(&self) -> &Rc<Env> {
+        self.env
+    }
+
+    /// The top-level environment, at the end of the caller's chain of `outer` environments.
+    pub fn root_env(&self) -> Rc<Env> {
+        let mut env = self.env.clone();
+        while let Some(outer) = env.outer.clone() {
+            env = outer;
+        }
+        env
+    }
+
+    /// Evaluates `ast` in the top-level environment, as the `eval` builtin does.
+    pub fn eval(&self, ast: MalType) -> Result<MalType, MalErr> {
+        crate::eval::eval(ast, &self.root_env())
+    }
+
+    /// Calls either kind of function with already evaluated `args`.
+    pub fn apply(&self, f: &MalType, args: Vec<MalType>) -> Result<MalType, MalErr> {
+        crate::eval::apply(f, args, self.env)
+    }
+}
+
+thread_local! {
+    /// The atoms [`MalType::pr_str`] is in the middle of printing.
+    static PRINTING_ATOMS: RefCell<Vec<*const RefCell<MalType>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A hash-map key. A string and a keyword with the same text are different keys.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Str(String),
+    /// Includes the leading `:`, as [`MalType::Keyword`] does.
+    Keyword(String),
+}
+
 #[derive(Clone)]
 pub enum MalType {
     Nil,
     Bool(bool),
-    Int(i32),
+    Int(i64),
+    /// An integer too large for `Int`. Created with [`MalType::bignum`], which keeps smaller
+    /// ones as `Int`.
+    Bignum(Rc<BigInt>),
+    /// An exact fraction that is not a whole number. Created with [`MalType::ratio`], which
+    /// turns whole numbers into integers.
+    Ratio(Rc<BigRational>),
+    Float(f64),
     Str(String),
-    Sym(String),
+    /// A symbol and, if it was read from a source, where. Symbols compare by name only.
+    Sym(String, Option<Rc<Span>>),
     Keyword(String),
-    List(Rc<Vec<MalType>>),
-    Func(fn(&[MalType]) -> Result<MalType, MalErr>),
+    /// Lists, vectors and hash-maps also keep where they were read, at their opening bracket.
+    List(Rc<Vec<MalType>>, Option<Rc<Span>>),
+    Vector(Rc<Vec<MalType>>, Option<Rc<Span>>),
+    /// Keys are made with [`MalType::to_key`] and turned back with [`MalType::from_key`].
+    HashMap(Rc<HashMap<MapKey, MalType>>, Option<Rc<Span>>),
+    Func {
+        name: String,
+        f: NativeFn,
+    },
     MalFunc {
         env: Rc<Env>,
         params: Vec<MalType>,
-        body: Box<MalType>,
+        body: Rc<MalType>,
+        is_macro: bool,
+    },
+    Atom(Rc<RefCell<MalType>>),
+    /// A host value passed through Mal code untouched. Created with [`MalType::opaque`] and
+    /// read back with [`MalType::downcast_ref`].
+    Opaque {
+        type_name: &'static str,
+        value: Rc<dyn Any>,
     },
 }
 
@@ -22,27 +106,286 @@ pub enum MalType {
 pub enum MalErr {
     ParseErr(String),
     E(String),
-    FuncNotFound,
-    WrongNumberOfArguments,
-    UnexpectedToken,
+    SymbolNotFound(String),
+    WrongNumberOfArguments {
+        name: String,
+        expected: String,
+        found: usize,
+    },
+    WrongType {
+        expected: String,
+        found: MalType,
+    },
+    UnexpectedToken {
+        expected: String,
+        found: String,
+    },
+    /// A value raised by `throw`, to be bound by `catch*`.
+    Throw(MalType),
+    /// `err` came up while reading or evaluating the source at `span`.
+    At {
+        span: Span,
+        err: Box<MalErr>,
+    },
+}
+
+impl MalErr {
+    /// The error without the source positions wrapped around it.
+    pub fn into_inner(self) -> MalErr {
+        match self {
+            Self::At { err, .. } => err.into_inner(),
+            err => err,
+        }
+    }
+
+    /// Points the error at `span`, unless it already points somewhere.
+    pub fn at(self, span: Option<Span>) -> MalErr {
+        match (self, span) {
+            (err @ Self::At { .. }, _) | (err, None) => err,
+            (err, Some(span)) => Self::At {
+                span,
+                err: Box::new(err),
+            },
+        }
+    }
+}
+
+impl fmt::Display for MalErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ParseErr(s) => write!(f, "Parse error: {s}"),
+            Self::E(s) => write!(f, "{s}"),
+            Self::SymbolNotFound(s) => write!(f, "Unable to find `{s}' in current environment"),
+            Self::WrongNumberOfArguments {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Wrong number of arguments to `{name}': expected {expected}, found {found}"
+            ),
+            Self::WrongType { expected, found } => {
+                write!(f, "Expected {expected} but found {}", found.pr_str(true))
+            }
+            Self::UnexpectedToken { expected, found } => {
+                write!(f, "Expected {expected} but found {found}")
+            }
+            Self::Throw(val) => write!(f, "Uncaught exception: {}", val.pr_str(true)),
+            Self::At { span, err } => write!(
+                f,
+                "{}:{}:{}: {err}\n{}",
+                span.source.name,
+                span.line,
+                span.col,
+                span.snippet()
+            ),
+        }
+    }
+}
+
+impl fmt::Debug for MalType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.pr_str(true))
+    }
+}
+
+/// Deep structural equality, as `=` does it. A list equals a vector with the same elements, but
+/// an integer never equals a float. Functions, atoms and opaque values are only equal to
+/// themselves, and NaN to nothing.
+impl PartialEq for MalType {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Nil, Self::Nil) => true,
+            (Self::Bool(a), Self::Bool(b)) => a == b,
+            (Self::Int(a), Self::Int(b)) => a == b,
+            (Self::Bignum(a), Self::Bignum(b)) => a == b,
+            (Self::Ratio(a), Self::Ratio(b)) => a == b,
+            (Self::Float(a), Self::Float(b)) => a == b,
+            (Self::Str(a), Self::Str(b)) => a == b,
+            (Self::Sym(a, _), Self::Sym(b, _)) => a == b,
+            (Self::Keyword(a), Self::Keyword(b)) => a == b,
+            (Self::List(a, _) | Self::Vector(a, _), Self::List(b, _) | Self::Vector(b, _)) => {
+                a == b
+            }
+            (Self::HashMap(a, _), Self::HashMap(b, _)) => a == b,
+            (Self::Func { f: a, .. }, Self::Func { f: b, .. }) => Rc::ptr_eq(a, b),
+            (
+                Self::MalFunc {
+                    body: a,
+                    is_macro: a_macro,
+                    ..
+                },
+                Self::MalFunc {
+                    body: b,
+                    is_macro: b_macro,
+                    ..
+                },
+            ) => Rc::ptr_eq(a, b) && a_macro == b_macro,
+            (Self::Atom(a), Self::Atom(b)) => Rc::ptr_eq(a, b),
+            (Self::Opaque { value: a, .. }, Self::Opaque { value: b, .. }) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
 }
 
 impl MalType {
-    pub fn pr_str(&self) -> String {
+    /// With `print_readably` strings are quoted and escaped so that the output can be read
+    /// back in, as `pr-str` and `prn` do. Without it they are printed as is, for `str` and
+    /// `println`.
+    pub fn pr_str(&self, print_readably: bool) -> String {
         match self {
             Self::Nil => "nil".to_string(),
             Self::Bool(true) => "true".to_string(),
             Self::Bool(false) => "false".to_string(),
             Self::Int(num) => format!("{num}"),
+            Self::Bignum(num) => format!("{num}"),
+            Self::Ratio(num) => format!("{num}"),
+            Self::Float(num) if num.is_nan() => "##NaN".to_string(),
+            Self::Float(num) if num.is_infinite() => {
+                if *num > 0.0 { "##Inf" } else { "##-Inf" }.to_string()
+            }
+            // Debug rather than Display keeps the `.0` of whole numbers and switches to an
+            // exponent for very large and small ones, so that the output reads back the same.
+            Self::Float(num) => format!("{num:?}"),
+            Self::Str(s) if print_readably => {
+                let escaped = s
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n");
+                format!("\"{escaped}\"")
+            }
             Self::Str(s) => s.clone(),
-            Self::Sym(s) => s.clone(),
+            Self::Sym(s, _) => s.clone(),
             Self::Keyword(s) => s.clone(),
-            Self::List(list) => {
-                let ret: Vec<String> = list.iter().map(|x| x.pr_str()).collect();
+            Self::List(list, _) => {
+                let ret: Vec<String> = list.iter().map(|x| x.pr_str(print_readably)).collect();
                 format!("{}{}{}", "(", ret.join(" "), ")")
             }
-            Self::Func(_f) => "<std:fn>".to_string(),
+            Self::Vector(vec, _) => {
+                let ret: Vec<String> = vec.iter().map(|x| x.pr_str(print_readably)).collect();
+                format!("{}{}{}", "[", ret.join(" "), "]")
+            }
+            Self::HashMap(map, _) => {
+                let ret: Vec<String> = map
+                    .iter()
+                    .map(|(k, v)| {
+                        format!(
+                            "{} {}",
+                            Self::from_key(k).pr_str(print_readably),
+                            v.pr_str(print_readably)
+                        )
+                    })
+                    .collect();
+                format!("{}{}{}", "{", ret.join(" "), "}")
+            }
+            Self::Func { name, .. } => format!("<std:fn {name}>"),
+            Self::MalFunc { is_macro: true, .. } => "<user:macro>".to_string(),
             Self::MalFunc { .. } => "<user:fn>".to_string(),
+            Self::Atom(val) => {
+                // An atom can end up holding itself, so one that is already being printed
+                // further out is not followed again.
+                let ptr = Rc::as_ptr(val);
+                if PRINTING_ATOMS.with(|atoms| atoms.borrow().contains(&ptr)) {
+                    return "(atom ...)".to_string();
+                }
+                PRINTING_ATOMS.with(|atoms| atoms.borrow_mut().push(ptr));
+                let inner = val.borrow().pr_str(print_readably);
+                PRINTING_ATOMS.with(|atoms| atoms.borrow_mut().pop());
+                format!("(atom {inner})")
+            }
+            Self::Opaque { type_name, .. } => format!("<opaque:{type_name}>"),
+        }
+    }
+
+    /// An integer, as an `Int` when it fits.
+    pub fn bignum(num: BigInt) -> MalType {
+        match num.to_i64() {
+            Some(num) => Self::Int(num),
+            None => Self::Bignum(Rc::new(num)),
+        }
+    }
+
+    /// An exact number, as an integer when it is a whole one.
+    pub fn ratio(num: BigRational) -> MalType {
+        match num.is_integer() {
+            true => Self::bignum(num.to_integer()),
+            false => Self::Ratio(Rc::new(num)),
+        }
+    }
+
+    /// A native function that only looks at its arguments.
+    pub fn func<F>(name: &str, f: F) -> MalType
+    where
+        F: Fn(&[MalType]) -> Result<MalType, MalErr> + 'static,
+    {
+        Self::func_with_context(name, move |_, args| f(args))
+    }
+
+    /// A native function that can call back into the interpreter through its [`Context`].
+    pub fn func_with_context<F>(name: &str, f: F) -> MalType
+    where
+        F: Fn(&Context, &[MalType]) -> Result<MalType, MalErr> + 'static,
+    {
+        Self::Func {
+            name: name.to_string(),
+            f: Rc::new(f),
+        }
+    }
+
+    pub fn opaque<T: Any>(value: T) -> MalType {
+        Self::opaque_rc(Rc::new(value))
+    }
+
+    pub fn opaque_rc<T: Any>(value: Rc<T>) -> MalType {
+        Self::Opaque {
+            type_name: any::type_name::<T>(),
+            value,
+        }
+    }
+
+    /// The host value if this is an opaque value holding a `T`.
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        match self {
+            Self::Opaque { value, .. } => value.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    /// Like [`MalType::downcast_ref`], sharing the host value instead of borrowing it.
+    pub fn downcast_rc<T: Any>(&self) -> Option<Rc<T>> {
+        match self {
+            Self::Opaque { value, .. } => value.clone().downcast().ok(),
+            _ => None,
+        }
+    }
+
+    /// Hash-map key for a string or keyword.
+    pub fn to_key(&self) -> Result<MapKey, MalErr> {
+        match self {
+            Self::Str(s) => Ok(MapKey::Str(s.clone())),
+            Self::Keyword(k) => Ok(MapKey::Keyword(k.clone())),
+            _ => Err(MalErr::WrongType {
+                expected: "string or keyword as hash-map key".to_string(),
+                found: self.clone(),
+            }),
+        }
+    }
+
+    pub fn from_key(key: &MapKey) -> MalType {
+        match key {
+            MapKey::Str(s) => Self::Str(s.clone()),
+            MapKey::Keyword(k) => Self::Keyword(k.clone()),
+        }
+    }
+
+    /// Where the form was read. `None` for anything else and for forms built at runtime.
+    pub(crate) fn span(&self) -> Option<Span> {
+        match self {
+            Self::Sym(_, span)
+            | Self::List(_, span)
+            | Self::Vector(_, span)
+            | Self::HashMap(_, span) => span.as_deref().cloned(),
+            _ => None,
         }
     }
 }
@@ -0,0 +1,61 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn mal(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mal"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start mal");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stdout).into_owned()
+}
+
+#[test]
+fn eval_expression() {
+    let out = mal(&["-e", "(list (+ 1 2) *ARGV*)", "a", "b"], "");
+    assert!(out.status.success());
+    assert_eq!("(3 (\"a\" \"b\"))\n", stdout(&out));
+
+    let out = mal(&["-e", "(undefined)"], "");
+    assert_eq!(Some(1), out.status.code());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("undefined"));
+
+    let out = mal(&["-e"], "");
+    assert_eq!(Some(2), out.status.code());
+}
+
+#[test]
+fn script_file() {
+    let path = std::env::temp_dir().join(format!("mal-cli-{}.mal", std::process::id()));
+    std::fs::write(&path, "(def! a 1)\n; comment\n(prn (+ a 1) *ARGV*)\n").unwrap();
+    let out = mal(&[path.to_str().unwrap(), "x"], "");
+    std::fs::remove_file(&path).unwrap();
+    assert!(out.status.success());
+    assert_eq!("2 (\"x\")\n", stdout(&out));
+
+    let out = mal(&["/nonexistent/script.mal"], "");
+    assert_eq!(Some(1), out.status.code());
+}
+
+#[test]
+fn non_interactive_stdin() {
+    let out = mal(&[], "(println \"hi\")\n(prn *ARGV*)\n");
+    assert!(out.status.success());
+    assert_eq!("hi\n()\n", stdout(&out));
+
+    let out = mal(&[], "(println 1) (throw \"boom\") (println 2)");
+    assert_eq!(Some(1), out.status.code());
+    assert_eq!("1\n", stdout(&out));
+}